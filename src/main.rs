@@ -21,6 +21,11 @@
 
 extern crate rustyline;
 extern crate ansi_term;
+#[cfg(unix)]
+extern crate libc;
+#[cfg(test)]
+#[macro_use]
+extern crate proptest;
 
 use rustyline::completion::FilenameCompleter;
 use rustyline::{Config, Editor, CompletionType, EditMode};
@@ -29,10 +34,200 @@ mod parser;
 mod runner;
 mod util;
 
-use runner::command::Runnable;
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use parser::ParseError;
+use runner::shell::Shell;
 use util::{prompt, history};
 
+static USAGE: &'static str = "\
+Usage: rusth [options] [script [args...]]
+       rusth [options] -c command [name [args...]]
+
+Options:
+  -c              Run the command given as the first argument
+  -i              Start an interactive shell even if the input is not a terminal
+  -l, --login     Act as a login shell
+  -e              Exit when a command fails, same as -o errexit
+  -x              Print commands before running them, same as -o xtrace
+  -o option       Set an option: errexit, xtrace, autocd
+      --norc      Do not read ~/.rusthrc
+      --rcfile PATH
+                  Read PATH instead of ~/.rusthrc
+      --version   Print the version and exit
+      --help      Print this help and exit";
+
+/// How the shell was started, parsed from the command line
+#[derive(Default)]
+struct Invocation {
+    /// The first argument is the command to run, `-c`
+    command: bool,
+    interactive: bool,
+    login: bool,
+    norc: bool,
+    rcfile: Option<String>,
+    /// Script or command with the arguments after it
+    operands: Vec<String>,
+}
+
+/// Parses the arguments after the program name, shell options are set directly on the shell
+fn parse_args(args: &[String], shell: &mut Shell) -> Result<Invocation, String> {
+    let mut invocation = Invocation::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--" | "-" => break,
+            "--login" => invocation.login = true,
+            "--norc" => invocation.norc = true,
+            "--rcfile" => match args.next() {
+                Some(path) => invocation.rcfile = Some(path.clone()),
+                None => return Err("--rcfile: option requires an argument".to_string())
+            },
+            "--version" => {
+                println!("rusth {}", env!("CARGO_PKG_VERSION"));
+                process::exit(0);
+            }
+            "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            long if long.starts_with("--") => return Err(format!("{}: invalid option", long)),
+            short if short.starts_with('-') => {
+                for c in short.chars().skip(1) {
+                    match c {
+                        'c' => invocation.command = true,
+                        'i' => invocation.interactive = true,
+                        'l' => invocation.login = true,
+                        'e' => shell.options.errexit = true,
+                        'x' => shell.options.xtrace = true,
+                        'o' => {
+                            let name = args.next().ok_or_else(|| "-o: option requires an argument".to_string())?;
+                            shell.options.set(name, true)?;
+                        }
+                        _ => return Err(format!("-{}: invalid option", c))
+                    }
+                }
+            }
+            operand => {
+                invocation.operands.push(operand.to_string());
+                break;
+            }
+        }
+    }
+    invocation.operands.extend(args.cloned());
+    Ok(invocation)
+}
+
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut shell = Shell::new();
+    // `execve` allows an empty argument list, without even the program name
+    let invocation = match parse_args(args.get(1..).unwrap_or(&[]), &mut shell) {
+        // A program name starting with `-` is the usual way to start a login shell
+        Ok(mut invocation) => {
+            invocation.login |= args.first().map_or(false, |name| name.starts_with('-'));
+            invocation
+        }
+        Err(e) => {
+            eprintln!("rusth: {}", e);
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    let is_interactive = !invocation.command && invocation.operands.is_empty() &&
+        (invocation.interactive || stdin_is_tty());
+    shell.interactive = is_interactive;
+    shell.login = invocation.login;
+    run_startup_files(&mut shell, &invocation, is_interactive);
+    let mut operands = invocation.operands.into_iter();
+    let code = if let Some(code) = shell.exit_requested() {
+        // `exit` in a startup file
+        code
+    } else if invocation.command {
+        // `rusth -c command [name [args...]]`
+        match operands.next() {
+            Some(command) => {
+                if let Some(name) = operands.next() {
+                    shell.name = name;
+                }
+                shell.positional = operands.collect();
+                let name = shell.name.clone();
+                runner::run_lines(&mut shell, &name, command.lines().map(|line| Ok(line.to_string())))
+            }
+            None => {
+                eprintln!("rusth: -c: option requires an argument");
+                Some(2)
+            }
+        }
+    } else if let Some(path) = operands.next() {
+        // `rusth script [args...]`
+        shell.name = path.clone();
+        shell.positional = operands.collect();
+        run_file(&mut shell, Path::new(&path))
+    } else if is_interactive {
+        interactive(&mut shell)
+    } else {
+        let name = shell.name.clone();
+        runner::run_stdin(&mut shell, &name)
+    };
+    shell.run_exit_trap();
+    process::exit(code.unwrap_or(1));
+}
+
+/// Runs a script file, its status is 127 if it cannot be read
+fn run_file(shell: &mut Shell, path: &Path) -> Option<i32> {
+    match File::open(path) {
+        Ok(file) => runner::run_lines(shell, &path.display().to_string(), BufReader::new(file).lines()),
+        Err(e) => {
+            eprintln!("rusth: {}: {}", path.display(), e);
+            Some(127)
+        }
+    }
+}
+
+/// Runs the profiles for login shells and the rc file for interactive ones,
+/// errors in them are reported but the shell still starts unless they `exit`
+fn run_startup_files(shell: &mut Shell, invocation: &Invocation, interactive: bool) {
+    if invocation.login {
+        run_file_if_exists(shell, Path::new("/etc/profile"));
+        let profile = history::get_home_file_path(".rusth_profile");
+        if profile.is_file() {
+            run_file(shell, &profile);
+        } else {
+            run_file_if_exists(shell, &history::get_home_file_path(".profile"));
+        }
+    }
+    if interactive && !invocation.norc {
+        let rc = match invocation.rcfile {
+            Some(ref path) => PathBuf::from(path),
+            None => history::get_home_file_path(".rusthrc")
+        };
+        run_file_if_exists(shell, &rc);
+    }
+}
+
+fn run_file_if_exists(shell: &mut Shell, path: &Path) {
+    if path.is_file() {
+        run_file(shell, path);
+    }
+}
+
+#[cfg(unix)]
+fn stdin_is_tty() -> bool {
+    unsafe { libc::isatty(0) == 1 }
+}
+
+#[cfg(not(unix))]
+fn stdin_is_tty() -> bool {
+    true
+}
+
+/// Reads commands with the line editor until the user exits, returns the last status
+fn interactive(shell: &mut Shell) -> Option<i32> {
     let mut prompt = prompt::Prompt::new();
 
     let config = Config::builder()
@@ -50,12 +245,24 @@ fn main() {
     if rl.load_history(&history_path).is_err() {
         println!("No previous history, creating history in {}", history_path.display());
     }
+    // Lines of a command that needs more input, such as an unterminated if
+    let mut pending = String::new();
     'read_loop: loop {
-        let line = match rl.readline(&prompt.make_prompt()) {
+        shell.reap_background();
+        let line_prompt = if pending.is_empty() {
+            prompt.make_prompt()
+        } else {
+            prompt.make_continuation_prompt()
+        };
+        let line = match rl.readline(&line_prompt) {
             Ok(line) => line,
             Err(err) => {
                 use rustyline::error::ReadlineError::*;
                 match err {
+                    Interrupted if !pending.is_empty() => {
+                        pending.clear();
+                        continue 'read_loop;
+                    }
                     Interrupted => println!("CTRL-C"),
                     Eof => println!("CTRL-D"),
                     _ => {
@@ -66,29 +273,33 @@ fn main() {
                 break 'read_loop;
             }
         };
+        pending.push_str(&line);
 
         // Parse the line into command / arguments
-        let mut parsed = match parser::parse(&line) {
-            Ok(mut parsed) => parsed,
+        let parsed = match parser::parse(&pending) {
+            Ok(parsed) => parsed,
+            Err(ParseError::Incomplete(_)) => {
+                pending.push('\n');
+                continue 'read_loop;
+            }
             Err(e) => {
                 println!("Error occured in command: {}", e);
+                rl.add_history_entry(pending.as_ref());
+                pending.clear();
                 continue 'read_loop;
             }
         };
 
-        // Exit if exit is entered
-        if let Runnable::Cmd(ref mut cmd) = parsed {
-            if !cmd.empty() && cmd.command() == "exit" {
-                break 'read_loop;
-            }
-        }
-
-        // Add to history if not exit
-        rl.add_history_entry(line.as_ref());
+        rl.add_history_entry(pending.as_ref());
+        pending.clear();
         // Run the parsed input
-        let code = runner::run_command(&mut parsed);
+        let code = runner::run_command(shell, &parsed);
         prompt.set_return_code(code);
+        if shell.exit_requested().is_some() {
+            break 'read_loop;
+        }
     }
     rl.save_history(&history_path).unwrap();
+    shell.exit_requested().unwrap_or(shell.last_status)
 }
 
@@ -16,30 +16,160 @@
  * limitations under the License.
 */
 use std;
-use std::str::Chars;
+use std::fmt;
+use std::str::CharIndices;
 use std::borrow::Cow;
 
-#[derive(Debug)]
+use runner::vars::is_valid_name;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token<'a> {
     Str(StrToken<'a>),
     Pipe,
     Insert,
     From,
     Append,
+    /// `;`
+    Semi,
+    /// `;;`, terminates a case item
+    DoubleSemi,
+    NewLine,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+    LParen,
+    RParen,
+    /// A single `&`
+    Background,
+    /// `>&`
+    DupOutput,
+    /// `<&`
+    DupInput,
+    /// File descriptor number written just before a redirection, like the `2` in `2>`
+    IoNumber(i32),
+    /// `((expr))`, only the expression is kept
+    Arith(Cow<'a, str>),
+    /// `[[ expr ]]`, with the raw words of the expression
+    Cond(Vec<Cow<'a, str>>),
 }
 
-#[derive(Debug)]
+impl<'a> fmt::Display for Token<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            Token::Str(ref s) => s.content.as_ref(),
+            Token::Pipe => "|",
+            Token::Insert => ">",
+            Token::From => "<",
+            Token::Append => ">>",
+            Token::Semi => ";",
+            Token::DoubleSemi => ";;",
+            Token::NewLine => "newline",
+            Token::And => "&&",
+            Token::Or => "||",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::Background => "&",
+            Token::DupOutput => ">&",
+            Token::DupInput => "<&",
+            Token::IoNumber(n) => return write!(f, "`{}`", n),
+            Token::Arith(ref expr) => return write!(f, "`(({}))`", expr),
+            Token::Cond(ref words) => return write!(f, "`[[ {} ]]`", words.join(" ")),
+        };
+        write!(f, "`{}`", s)
+    }
+}
+
+/// Words that have a special meaning when they appear unquoted in command position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reserved {
+    If,
+    Then,
+    Elif,
+    Else,
+    Fi,
+    Case,
+    Esac,
+    In,
+    For,
+    While,
+    Until,
+    Do,
+    Done,
+    Function,
+    LBrace,
+    RBrace,
+    /// `!` before a pipeline
+    Bang,
+}
+
+/// A single word, `content` is the raw text including any quotes,
+/// quote removal is done while expanding the word before it is run
+#[derive(Debug, Clone, PartialEq)]
 pub struct StrToken<'a> {
     pub content: Cow<'a, str>
 }
 
-type LexerIterPeekable<'a> = std::iter::Peekable<Chars<'a>>;
+impl fmt::Display for Reserved {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Reserved::*;
+        let s = match *self {
+            If => "if",
+            Then => "then",
+            Elif => "elif",
+            Else => "else",
+            Fi => "fi",
+            Case => "case",
+            Esac => "esac",
+            In => "in",
+            For => "for",
+            While => "while",
+            Until => "until",
+            Do => "do",
+            Done => "done",
+            Function => "function",
+            LBrace => "{",
+            RBrace => "}",
+            Bang => "!",
+        };
+        write!(f, "`{}`", s)
+    }
+}
+
+impl<'a> StrToken<'a> {
+    pub fn reserved(&self) -> Option<Reserved> {
+        use self::Reserved::*;
+        match self.content.as_ref() {
+            "if" => Some(If),
+            "then" => Some(Then),
+            "elif" => Some(Elif),
+            "else" => Some(Else),
+            "fi" => Some(Fi),
+            "case" => Some(Case),
+            "esac" => Some(Esac),
+            "in" => Some(In),
+            "for" => Some(For),
+            "while" => Some(While),
+            "until" => Some(Until),
+            "do" => Some(Do),
+            "done" => Some(Done),
+            "function" => Some(Function),
+            "{" => Some(LBrace),
+            "}" => Some(RBrace),
+            "!" => Some(Bang),
+            _ => None
+        }
+    }
+}
+
+type LexerIterPeekable<'a> = std::iter::Peekable<CharIndices<'a>>;
 type OptionalResult<T, E> = Result<Option<T>, E>;
 
 pub struct Lexer<'a> {
     pub line: &'a str,
     pub iter: LexerIterPeekable<'a>,
     pub tokens: Vec<Token<'a>>,
+    /// Byte offset of the next unconsumed char in `line`
     loc: usize,
 }
 
@@ -47,12 +177,14 @@ impl<'a> Lexer<'a> {
     pub fn new(line: &'a str) -> Lexer {
         Lexer {
             line,
-            iter: line.chars().peekable(),
+            iter: line.char_indices().peekable(),
             tokens: Vec::new(),
             loc: 0,
         }
     }
 
+    /// Splits the line into tokens, an error returned when the lexer is `finished` means
+    /// that the input ended inside a quote or an escape, otherwise the input is invalid
     pub fn lex_tokens<'err>(&mut self) -> Result<(), &'err str> {
         loop {
             let token = self.next_token();
@@ -67,11 +199,14 @@ impl<'a> Lexer<'a> {
 
     fn next_token<'err>(&mut self) -> OptionalResult<Token<'a>, &'err str> {
         self.consume_ws();
+        if self.peek() == Some('#') {
+            self.take_while(|c| c != '\n');
+        }
         if self.finished() {
             return Ok(None);
         }
-        match self.iter.peek() {
-            Some(&c) => self.next_token_with(c),
+        match self.peek() {
+            Some(c) => self.next_token_with(c),
             // Shouldn't happen in reality thanks to self.finished() check above
             None => Err("Unknown Error")
         }
@@ -79,89 +214,297 @@ impl<'a> Lexer<'a> {
 
     fn next_token_with<'err>(&mut self, c: char) -> OptionalResult<Token<'a>, &'err str> {
         match c {
-            '\"' => {
+            '\n' => {
                 self.next();
-                self.next_double_quote()
+                Ok(Some(Token::NewLine))
             }
             '|' => {
                 self.next();
-                Ok(Some(Token::Pipe))
+                Ok(Some(self.if_next('|', Token::Or, Token::Pipe)))
+            }
+            '&' => {
+                self.next();
+                Ok(Some(self.if_next('&', Token::And, Token::Background)))
+            }
+            ';' => {
+                self.next();
+                Ok(Some(self.if_next(';', Token::DoubleSemi, Token::Semi)))
             }
+            '(' => {
+                self.next();
+                if self.next_if('(') {
+                    self.next_arith()
+                } else {
+                    Ok(Some(Token::LParen))
+                }
+            }
+            ')' => {
+                self.next();
+                Ok(Some(Token::RParen))
+            }
+            '[' if self.at_cond() => self.next_cond(),
+            '<' | '>' if self.at_process_sub() => self.next_word(),
             '>' => {
                 self.next();
-                let is_append = {
-                    if let Some(c) = self.peek() {
-                        *c == '>'
-                    } else {
-                        false
-                    }
-                };
-                if is_append {
-                    self.next();
-                    Ok(Some(Token::Append))
+                if self.next_if('&') {
+                    Ok(Some(Token::DupOutput))
                 } else {
-                    Ok(Some(Token::Insert))
+                    Ok(Some(self.if_next('>', Token::Append, Token::Insert)))
                 }
             }
             '<' => {
                 self.next();
-                Ok(Some(Token::From))
+                Ok(Some(self.if_next('&', Token::DupInput, Token::From)))
+            }
+            _ => self.next_word()
+        }
+    }
+
+    /// Consumes `c` and returns `then` if it is the next char, `otherwise` if not
+    fn if_next(&mut self, c: char, then: Token<'a>, otherwise: Token<'a>) -> Token<'a> {
+        if self.next_if(c) {
+            then
+        } else {
+            otherwise
+        }
+    }
+
+    /// Returns true at the start of a `<(cmd)` or `>(cmd)` process substitution
+    fn at_process_sub(&self) -> bool {
+        let rest = &self.line[self.loc..];
+        rest.starts_with("<(") || rest.starts_with(">(")
+    }
+
+    /// Returns true at a `[[` followed by whitespace where a command can start
+    fn at_cond(&self) -> bool {
+        let rest = &self.line[self.loc..];
+        if !rest.starts_with("[[") || !rest[2..].starts_with(char::is_whitespace) {
+            return false;
+        }
+        match self.tokens.last() {
+            None => true,
+            Some(&Token::Str(ref word)) => match word.reserved() {
+                Some(Reserved::If) | Some(Reserved::Then) | Some(Reserved::Elif) | Some(Reserved::Else) |
+                Some(Reserved::Do) | Some(Reserved::While) | Some(Reserved::Until) | Some(Reserved::LBrace) |
+                Some(Reserved::Bang) => true,
+                _ => false
+            },
+            Some(&Token::Semi) | Some(&Token::DoubleSemi) | Some(&Token::NewLine) | Some(&Token::And) |
+            Some(&Token::Or) | Some(&Token::Pipe) | Some(&Token::LParen) | Some(&Token::RParen) |
+            Some(&Token::Background) => true,
+            _ => false
+        }
+    }
+
+    fn next_if(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn next_word<'err>(&mut self) -> OptionalResult<Token<'a>, &'err str> {
+        let current_loc = self.loc;
+        while let Some(c) = self.peek() {
+            if self.loc == current_loc && self.at_process_sub() {
+                self.next();
+                self.next();
+                self.next_paren()?;
+                continue;
+            }
+            // The elements of an array assignment like `arr=(a b)` are part of the word
+            if c == '(' && is_assignment_prefix(&self.line[current_loc..self.loc]) {
+                self.next();
+                self.next_paren()?;
+                continue;
+            }
+            if is_word_end(c) {
+                break;
+            }
+            self.next();
+            match c {
+                '\\' => {
+                    if self.next().is_none() {
+                        return Err("Unexpected end of line after \\");
+                    }
+                }
+                '\'' => self.next_single_quote()?,
+                '"' => self.next_double_quote()?,
+                '$' if self.next_if('{') => self.next_brace()?,
+                '$' if self.next_if('(') => self.next_paren()?,
+                _ => {}
+            }
+        }
+        let content = &self.line[current_loc..self.loc];
+        let is_io_number = match self.peek() {
+            Some('<') | Some('>') => content.chars().all(|c| c.is_ascii_digit()),
+            _ => false
+        };
+        if is_io_number {
+            if let Ok(fd) = content.parse() {
+                return Ok(Some(Token::IoNumber(fd)));
             }
-            _ => {
-                let current_loc = self.loc;
-                let new_loc = self.take_while(|c| !c.is_whitespace());
-                Ok(Some(
-                    Token::Str(StrToken {
-                        content: self.line[current_loc..new_loc].into()
-                    })
-                ))
+        }
+        Ok(Some(
+            Token::Str(StrToken {
+                content: content.into()
+            })
+        ))
+    }
+
+    /// Consumes the rest of a `${...}` expansion
+    fn next_brace<'err>(&mut self) -> Result<(), &'err str> {
+        while let Some(c) = self.next() {
+            match c {
+                '\\' => {
+                    self.next();
+                }
+                '\'' => self.next_single_quote()?,
+                '"' => self.next_double_quote()?,
+                '$' if self.next_if('{') => self.next_brace()?,
+                '$' if self.next_if('(') => self.next_paren()?,
+                '}' => return Ok(()),
+                _ => {}
             }
         }
+        Err("Cannot find closing }")
     }
 
-    fn next_double_quote<'err>(&mut self) -> OptionalResult<Token<'a>, &'err str> {
-        let mut val = String::new();
-        'quot: while let Some(c) = self.next() {
+    /// Consumes the rest of a `$(...)` or `$((...))` up to the matching parenthesis
+    fn next_paren<'err>(&mut self) -> Result<(), &'err str> {
+        let mut depth = 0;
+        while let Some(c) = self.next() {
             match c {
                 '\\' => {
-                    let c = match self.next() {
-                        Some(c) => c,
-                        None => break 'quot
-                    };
-                    match c {
-                        '"' | '\\' => {
-                            val.push(c);
-                        }
-                        'n' => {
-                            val.push('\n');
-                        }
-                        _ => {
-                            val.push('\\');
-                            val.push(c);
-                        }
+                    self.next();
+                }
+                '\'' => self.next_single_quote()?,
+                '"' => self.next_double_quote()?,
+                '(' => depth += 1,
+                ')' if depth == 0 => return Ok(()),
+                ')' => depth -= 1,
+                _ => {}
+            }
+        }
+        Err("Cannot find closing )")
+    }
+
+    /// Consumes the rest of a `((expr))` and returns the expression in it
+    fn next_arith<'err>(&mut self) -> OptionalResult<Token<'a>, &'err str> {
+        let start = self.loc;
+        let mut depth = 0;
+        while let Some(c) = self.next() {
+            match c {
+                '(' => depth += 1,
+                ')' if depth > 0 => depth -= 1,
+                ')' if self.peek() == Some(')') => {
+                    let expr = &self.line[start..self.loc - 1];
+                    self.next();
+                    return Ok(Some(Token::Arith(expr.into())));
+                }
+                _ => {}
+            }
+        }
+        Err("Cannot find closing ))")
+    }
+
+    /// Consumes the rest of a `[[ ... ]]` and returns its words, where `&&`, `||`,
+    /// parentheses, `<` and `>` are separate words, except in the regular expression after `=~`
+    fn next_cond<'err>(&mut self) -> OptionalResult<Token<'a>, &'err str> {
+        self.next();
+        self.next();
+        let mut words: Vec<Cow<'a, str>> = Vec::new();
+        loop {
+            self.take_while(char::is_whitespace);
+            let start = self.loc;
+            match self.peek() {
+                None => return Err("Cannot find closing ]]"),
+                Some(_) if words.last().map_or(false, |word| word == "=~") => {
+                    // `]]` would otherwise be taken as the pattern and the expression never closed
+                    let rest = &self.line[self.loc..];
+                    if rest.starts_with("]]") && rest[2..].chars().next().map_or(true, is_word_end) {
+                        return Err("Expected a regular expression after =~");
+                    }
+                    self.next_regex()?
+                }
+                Some(c @ '&') | Some(c @ '|') => {
+                    self.next();
+                    self.next_if(c);
+                }
+                Some('(') | Some(')') | Some('<') | Some('>') => {
+                    self.next();
+                }
+                Some(c) if is_word_end(c) => return Err("Unexpected ; in [[ ]]"),
+                Some(_) => {
+                    self.next_word()?;
+                    // Nothing else may end a word here, but an empty one would never move on
+                    if self.loc == start {
+                        return Err("Unexpected character in [[ ]]");
                     }
                 }
-                '\"' => {
-                    return Ok(Some(
-                        Token::Str(StrToken {
-                            content: val.into()
-                        })
-                    ));
+            }
+            let word = &self.line[start..self.loc];
+            if word == "]]" {
+                return Ok(Some(Token::Cond(words)));
+            }
+            words.push(word.into());
+        }
+    }
+
+    /// Consumes a regular expression up to whitespace outside of parentheses
+    fn next_regex<'err>(&mut self) -> Result<(), &'err str> {
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            if depth == 0 && c.is_whitespace() {
+                break;
+            }
+            self.next();
+            match c {
+                '\\' => {
+                    self.next();
                 }
-                _ => {
-                    val.push(c);
+                '\'' => self.next_single_quote()?,
+                '"' => self.next_double_quote()?,
+                '$' if self.next_if('{') => self.next_brace()?,
+                '(' => depth += 1,
+                ')' if depth > 0 => depth -= 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn next_single_quote<'err>(&mut self) -> Result<(), &'err str> {
+        while let Some(c) = self.next() {
+            if c == '\'' {
+                return Ok(());
+            }
+        }
+        Err("Cannot find closing \'")
+    }
+
+    fn next_double_quote<'err>(&mut self) -> Result<(), &'err str> {
+        while let Some(c) = self.next() {
+            match c {
+                '\\' => {
+                    self.next();
                 }
+                '$' if self.next_if('(') => self.next_paren()?,
+                '"' => return Ok(()),
+                _ => {}
             }
         }
         Err("Cannot find closing \"")
     }
 
     fn consume_ws(&mut self) -> usize {
-        self.take_while(|c| c.is_whitespace())
+        self.take_while(|c| c.is_whitespace() && c != '\n')
     }
 
     fn take_while(&mut self, pred: fn(char) -> bool) -> usize {
-        while let Some(&c) = self.peek() {
+        while let Some(c) = self.peek() {
             if !pred(c) {
                 break;
             }
@@ -170,15 +513,16 @@ impl<'a> Lexer<'a> {
         self.loc
     }
 
-    fn peek(&mut self) -> Option<&char> {
-        self.iter.peek()
+    fn peek(&mut self) -> Option<char> {
+        self.iter.peek().map(|&(_, c)| c)
     }
     fn next(&mut self) -> Option<char> {
-        self.loc += 1;
-        self.iter.next()
+        let (idx, c) = self.iter.next()?;
+        self.loc = idx + c.len_utf8();
+        Some(c)
     }
 
-    fn finished(&self) -> bool {
+    pub fn finished(&self) -> bool {
         self.loc == self.line.len()
     }
 
@@ -186,3 +530,116 @@ impl<'a> Lexer<'a> {
         self.tokens
     }
 }
+
+fn is_word_end(c: char) -> bool {
+    c.is_whitespace() || "|&;()<>".contains(c)
+}
+
+/// Returns true for `name=` and `name[subscript]=`
+fn is_assignment_prefix(word: &str) -> bool {
+    if !word.ends_with('=') {
+        return false;
+    }
+    let name = &word[..word.len() - 1];
+    match name.find('[') {
+        Some(idx) => name.ends_with(']') && is_valid_name(&name[..idx]),
+        None => is_valid_name(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn lex(line: &str) -> Result<Vec<Token>, &str> {
+        let mut lexer = Lexer::new(line);
+        lexer.lex_tokens()?;
+        Ok(lexer.collect())
+    }
+
+    fn contents<'a>(tokens: &'a [Token]) -> Vec<&'a str> {
+        tokens.iter().map(|token| match *token {
+            Token::Str(ref s) => s.content.as_ref(),
+            _ => panic!("Unexpected token {:?}", token)
+        }).collect()
+    }
+
+    #[test]
+    fn multibyte_words() {
+        let tokens = lex("echo héllo 🦀.txt").unwrap();
+        assert_eq!(contents(&tokens), vec!["echo", "héllo", "🦀.txt"]);
+    }
+
+    #[test]
+    fn cond_words() {
+        let tokens = lex("[[ -n $a && ( b < c ) ]]").unwrap();
+        match tokens[0] {
+            Token::Cond(ref words) => assert_eq!(words, &["-n", "$a", "&&", "(", "b", "<", "c", ")"]),
+            ref token => panic!("Unexpected token {:?}", token)
+        }
+    }
+
+    #[test]
+    fn cond_semicolon() {
+        assert!(lex("[[ a ; ]]").is_err());
+        assert!(lex("[[ -n x; then").is_err());
+        assert!(lex("[[ a =~ ; ]]").is_ok());
+    }
+
+    #[test]
+    fn cond_missing_regex() {
+        assert!(lex("[[ a =~ ]]").is_err());
+        assert!(lex("[[ a =~ ]]; echo").is_err());
+        assert!(lex("[[ a =~ ]]x ]]").is_ok());
+    }
+
+    #[test]
+    fn cond_after_bang() {
+        let tokens = lex("if ! [[ -f x ]]; then").unwrap();
+        match tokens[2] {
+            Token::Cond(ref words) => assert_eq!(words, &["-f", "x"]),
+            ref token => panic!("Unexpected token {:?}", token)
+        }
+    }
+
+    #[test]
+    fn multibyte_quoted() {
+        let tokens = lex("echo \"ç ğ\" 'ı ş'ü").unwrap();
+        assert_eq!(contents(&tokens), vec!["echo", "\"ç ğ\"", "'ı ş'ü"]);
+    }
+
+    proptest! {
+        #[test]
+        fn never_panics(ref line in any::<String>()) {
+            let _ = lex(line);
+        }
+
+        #[test]
+        fn plain_words_match_split(ref line in "[^\"'\\\\|&;()<>#$\n\\[]*") {
+            let tokens = lex(line).unwrap();
+            let expected: Vec<&str> = line.split_whitespace().collect();
+            prop_assert_eq!(contents(&tokens), expected);
+        }
+
+        #[test]
+        fn quoted_word_is_single_token(ref inner in "[^\"\\\\$]*") {
+            let line = format!("\"{}\"", inner);
+            let tokens = lex(&line).unwrap();
+            prop_assert_eq!(contents(&tokens), vec![line.as_str()]);
+        }
+
+        #[test]
+        fn reserved_words_only_unquoted(ref word in "if|then|elif|else|fi|case|esac|in|for|while|until|do|done|function|\\{|\\}|!") {
+            let line = format!("{0} '{0}'", word);
+            let tokens = lex(&line).unwrap();
+            match (&tokens[0], &tokens[1]) {
+                (&Token::Str(ref plain), &Token::Str(ref quoted)) => {
+                    prop_assert!(plain.reserved().is_some());
+                    prop_assert!(quoted.reserved().is_none());
+                }
+                _ => panic!("Expected two words")
+            }
+        }
+    }
+}
@@ -19,16 +19,65 @@ mod lexer;
 #[allow(unknown_lints, module_inception)]
 mod parser;
 
-use self::lexer::Lexer;
+use std::fmt;
+
+use self::lexer::{Lexer, StrToken, Token};
 use self::parser::Parser;
 
 use runner::command::Runnable;
 
-pub fn parse(line: &str) -> Result<Runnable, String> {
+#[derive(Debug)]
+pub enum ParseError {
+    /// The input ended in the middle of a command, it may be completed by reading more lines
+    Incomplete(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::Incomplete(ref e) => write!(f, "Unexpected end of input: {}", e),
+            ParseError::Invalid(ref e) => write!(f, "{}", e)
+        }
+    }
+}
+
+pub fn parse(line: &str) -> Result<Runnable, ParseError> {
     let mut lexer = Lexer::new(line);
     if let Err(e) = lexer.lex_tokens() {
-        return Err(e.into());
+        return Err(lex_error(&lexer, e));
     };
     let parser = Parser::new(lexer.collect());
     parser.collect()
-}
\ No newline at end of file
+}
+
+/// Errors of the lexer mean that more input is needed only if it stopped at the end of the line
+fn lex_error(lexer: &Lexer, e: &str) -> ParseError {
+    if lexer.finished() {
+        ParseError::Incomplete(e.into())
+    } else {
+        ParseError::Invalid(e.into())
+    }
+}
+
+/// Returns true if the word is reserved, like `if` or `done`
+pub fn is_reserved_word(word: &str) -> bool {
+    StrToken { content: word.into() }.reserved().is_some()
+}
+
+/// Splits text into raw words, like the elements of an array assignment
+pub fn split_words(text: &str) -> Result<Vec<String>, ParseError> {
+    let mut lexer = Lexer::new(text);
+    if let Err(e) = lexer.lex_tokens() {
+        return Err(lex_error(&lexer, e));
+    };
+    let mut words = Vec::new();
+    for token in lexer.collect() {
+        match token {
+            Token::Str(word) => words.push(word.content.into_owned()),
+            Token::NewLine => {}
+            token => return Err(ParseError::Invalid(format!("Unexpected token {}", token)))
+        }
+    }
+    Ok(words)
+}
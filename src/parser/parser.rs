@@ -16,81 +16,714 @@
  * limitations under the License.
 */
 
-use super::lexer::Token;
-use std::borrow::Cow;
+use super::lexer::{Token, Reserved, StrToken};
+use super::ParseError;
 
 use runner::command::*;
+use runner::vars::is_valid_name;
+
+type ParseResult<T> = Result<T, ParseError>;
 
 pub struct Parser<'a> {
-    tokens: Vec<Token<'a>>
+    tokens: Vec<Token<'a>>,
+    pos: usize,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(tokens: Vec<Token<'a>>) -> Parser {
         Parser {
-            tokens
+            tokens,
+            pos: 0,
+        }
+    }
+
+    pub fn collect(mut self) -> ParseResult<Runnable> {
+        let list = self.parse_list(&[])?;
+        match self.peek() {
+            None => Ok(list),
+            Some(token) => Err(unexpected(token))
         }
     }
 
-    fn collect_single<I>(iter: &mut I) -> (Option<Cow<'a, str>>, Option<Token<'a>>)
-        where I: Iterator<Item=Token<'a>> {
-        match iter.next() {
-            Some(Token::Str(token)) => (Some(token.content), None),
-            Some(token) => (None, Some(token)),
-            None => (None, None)
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_reserved(&self) -> Option<Reserved> {
+        match self.peek() {
+            Some(&Token::Str(ref word)) => word.reserved(),
+            _ => None
         }
     }
 
-    fn collect_items<I>(iter: &mut I) -> (Vec<Cow<'a, str>>, Option<Token<'a>>)
-        where I: Iterator<Item=Token<'a>> {
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.tokens.get(self.pos).cloned();
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn skip_newlines(&mut self) {
+        while let Some(&Token::NewLine) = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect_reserved(&mut self, expected: Reserved) -> ParseResult<()> {
+        match self.next() {
+            Some(Token::Str(ref word)) if word.reserved() == Some(expected) => Ok(()),
+            Some(token) => Err(unexpected(&token)),
+            None => Err(incomplete(expected))
+        }
+    }
+
+    fn expect_word(&mut self) -> ParseResult<StrToken<'a>> {
+        match self.next() {
+            Some(Token::Str(word)) => Ok(word),
+            Some(token) => Err(unexpected(&token)),
+            None => Err(ParseError::Incomplete("Expected a word".to_string()))
+        }
+    }
+
+    /// Parses commands separated by `;` or new lines until one of the `terminators`
+    /// is found in command position, or a token that cannot start a command
+    fn parse_list(&mut self, terminators: &[Reserved]) -> ParseResult<Runnable> {
         let mut items = Vec::new();
-        for token in iter {
-            match token {
-                Token::Str(token) => items.push(token.content),
-                _ => return (items, Some(token))
-            }
-        }
-        (items, None)
-    }
-
-    pub fn collect(self) -> Result<Runnable<'a>, String> {
-        let iter = &mut self.tokens.into_iter();
-        let res = Parser::collect_items(iter);
-        let (items, token) = res;
-        match token {
-            Some(Token::Pipe) => {
-                let res_next = Parser::collect_items(iter);
-                let (items_next, token_next) = res_next;
-                match token_next {
-                    Some(token) => Err(format!("Unexpected token {:?}, commands more than 2 not implemented, will ignore the rest", token)),
-                    None => Ok(Runnable::Pipe(Pipe::new(Command::new(items), Command::new(items_next))))
+        loop {
+            self.skip_newlines();
+            if self.at_list_end(terminators) {
+                break;
+            }
+            items.push(self.parse_and_or()?);
+            match self.peek() {
+                Some(&Token::Semi) | Some(&Token::NewLine) => self.pos += 1,
+                Some(&Token::Background) => {
+                    self.pos += 1;
+                    let item = items.pop().unwrap();
+                    items.push(Runnable::Background(Background::new(item)));
                 }
+                _ => break
+            }
+        }
+        if items.len() == 1 {
+            Ok(items.pop().unwrap())
+        } else {
+            Ok(Runnable::List(items))
+        }
+    }
+
+    /// Same as `parse_list`, but the list cannot be empty
+    fn parse_compound_list(&mut self, terminators: &[Reserved]) -> ParseResult<Runnable> {
+        let list = self.parse_list(terminators)?;
+        if let Runnable::List(ref items) = list {
+            if items.is_empty() {
+                return Err(match self.peek() {
+                    Some(token) => unexpected(token),
+                    None => ParseError::Incomplete("Expected a command".to_string())
+                });
+            }
+        }
+        Ok(list)
+    }
+
+    fn at_list_end(&self, terminators: &[Reserved]) -> bool {
+        match self.peek() {
+            None | Some(&Token::DoubleSemi) | Some(&Token::RParen) => true,
+            Some(&Token::Str(ref word)) => word.reserved().map_or(false, |r| terminators.contains(&r)),
+            _ => false
+        }
+    }
+
+    fn parse_and_or(&mut self) -> ParseResult<Runnable> {
+        let first = self.parse_pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let connector = match self.peek() {
+                Some(&Token::And) => Connector::And,
+                Some(&Token::Or) => Connector::Or,
+                _ => break
+            };
+            self.pos += 1;
+            self.skip_newlines();
+            rest.push((connector, self.parse_pipeline()?));
+        }
+        if rest.is_empty() {
+            Ok(first)
+        } else {
+            Ok(Runnable::AndOr(AndOr::new(first, rest)))
+        }
+    }
+
+    fn parse_pipeline(&mut self) -> ParseResult<Runnable> {
+        if self.peek_reserved() == Some(Reserved::Bang) {
+            self.pos += 1;
+            return Ok(Runnable::Not(Box::new(self.parse_pipeline()?)));
+        }
+        let mut commands = vec![self.parse_command()?];
+        while let Some(&Token::Pipe) = self.peek() {
+            self.pos += 1;
+            self.skip_newlines();
+            commands.push(self.parse_command()?);
+        }
+        if commands.len() == 1 {
+            Ok(commands.pop().unwrap())
+        } else {
+            Ok(Runnable::Pipe(Pipe::new(commands)))
+        }
+    }
+
+    fn parse_command(&mut self) -> ParseResult<Runnable> {
+        if self.peek_reserved() == Some(Reserved::Function) || self.at_function_def() {
+            return self.parse_function();
+        }
+        let cmd = match self.parse_compound()? {
+            Some(cmd) => cmd,
+            None => return self.parse_simple()
+        };
+        let redirects = self.parse_redirects()?;
+        if redirects.is_empty() {
+            Ok(cmd)
+        } else {
+            Ok(Runnable::Redirected(Redirected::new(cmd, redirects)))
+        }
+    }
+
+    /// Parses a compound command, returns None if the next command is a simple one
+    fn parse_compound(&mut self) -> ParseResult<Option<Runnable>> {
+        match self.peek() {
+            Some(&Token::LParen) => return self.parse_subshell().map(Some),
+            Some(&Token::Arith(ref expr)) => {
+                let expr = expr.to_string();
+                self.pos += 1;
+                return Ok(Some(Runnable::Arith(Arith::new(expr))));
+            }
+            Some(&Token::Cond(ref words)) => {
+                let words = words.iter().map(|word| word.to_string()).collect();
+                self.pos += 1;
+                return Ok(Some(Runnable::Cond(Cond::new(words))));
+            }
+            _ => {}
+        }
+        let cmd = match self.peek_reserved() {
+            Some(Reserved::If) => self.parse_if()?,
+            Some(Reserved::Case) => self.parse_case()?,
+            Some(Reserved::For) => self.parse_for()?,
+            Some(Reserved::While) => self.parse_while(false)?,
+            Some(Reserved::Until) => self.parse_while(true)?,
+            Some(Reserved::LBrace) => self.parse_group()?,
+            Some(_) => return Err(unexpected(self.peek().unwrap())),
+            None => return Ok(None)
+        };
+        Ok(Some(cmd))
+    }
+
+    fn parse_redirects(&mut self) -> ParseResult<Vec<Redirect>> {
+        let mut redirects = Vec::new();
+        while let Some(redirect) = self.parse_redirect()? {
+            redirects.push(redirect);
+        }
+        Ok(redirects)
+    }
+
+    fn parse_group(&mut self) -> ParseResult<Runnable> {
+        self.expect_reserved(Reserved::LBrace)?;
+        let body = self.parse_compound_list(&[Reserved::RBrace])?;
+        self.expect_reserved(Reserved::RBrace)?;
+        Ok(Runnable::Group(Box::new(body)))
+    }
+
+    fn parse_subshell(&mut self) -> ParseResult<Runnable> {
+        self.pos += 1;
+        let body = self.parse_compound_list(&[])?;
+        match self.next() {
+            Some(Token::RParen) => Ok(Runnable::Subshell(Subshell::new(body))),
+            Some(token) => Err(unexpected(&token)),
+            None => Err(ParseError::Incomplete("Expected )".to_string()))
+        }
+    }
+
+    /// Returns true if the next tokens are `name ( )`
+    fn at_function_def(&self) -> bool {
+        match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1), self.tokens.get(self.pos + 2)) {
+            (Some(&Token::Str(_)), Some(&Token::LParen), Some(&Token::RParen)) => true,
+            _ => false
+        }
+    }
+
+    /// Parses `name() { body; }` or `function name [()] { body; }`
+    fn parse_function(&mut self) -> ParseResult<Runnable> {
+        if self.peek_reserved() == Some(Reserved::Function) {
+            self.pos += 1;
+        }
+        let name = self.expect_word()?.content.to_string();
+        if !is_valid_function_name(&name) {
+            return Err(ParseError::Invalid(format!("`{}': not a valid identifier", name)));
+        }
+        if let Some(&Token::LParen) = self.peek() {
+            self.pos += 1;
+            match self.next() {
+                Some(Token::RParen) => {}
+                Some(token) => return Err(unexpected(&token)),
+                None => return Err(ParseError::Incomplete("Expected )".to_string()))
             }
-            Some(Token::Insert) => {
-                match Parser::collect_single(iter) {
-                    (Some(file_name), None) => Ok(Runnable::Insert(Insert::new(Command::new(items), file_name))),
-                    (None, Some(token)) => Err(format!("Unexpected token {:?}", token)),
-                    (None, None) => Err("Expected token as filename!".to_string()),
-                    (Some(_), Some(_)) => unreachable!()
+        }
+        self.skip_newlines();
+        let mut body = match self.parse_compound()? {
+            Some(body) => body,
+            None => return Err(match self.peek() {
+                Some(token) => unexpected(token),
+                None => ParseError::Incomplete("Expected the function body".to_string())
+            })
+        };
+        // Redirections after the body are applied every time the function is called
+        let redirects = self.parse_redirects()?;
+        if !redirects.is_empty() {
+            body = Runnable::Redirected(Redirected::new(body, redirects));
+        }
+        Ok(Runnable::Function(FunctionDef::new(name, body)))
+    }
+
+    fn parse_simple(&mut self) -> ParseResult<Runnable> {
+        let mut assignments = Vec::new();
+        let mut items = Vec::new();
+        let mut redirects = Vec::new();
+        loop {
+            if let Some(&Token::Str(ref word)) = self.peek() {
+                // Assignments are only recognized before the command name
+                match Assignment::parse(&word.content) {
+                    Some(assignment) if items.is_empty() => assignments.push(assignment),
+                    _ => items.push(word.content.to_string())
                 }
+                self.pos += 1;
+                continue;
+            }
+            match self.parse_redirect()? {
+                Some(redirect) => redirects.push(redirect),
+                None => break
+            }
+        }
+        if assignments.is_empty() && items.is_empty() && redirects.is_empty() {
+            return Err(match self.peek() {
+                Some(token) => unexpected(token),
+                None => ParseError::Incomplete("Expected a command".to_string())
+            });
+        }
+        Ok(Runnable::Cmd(Command::new(assignments, items, redirects)))
+    }
+
+    /// Parses a redirection like `2> file` if there is one
+    fn parse_redirect(&mut self) -> ParseResult<Option<Redirect>> {
+        let io_number = match self.peek() {
+            Some(&Token::IoNumber(fd)) => {
+                self.pos += 1;
+                Some(fd)
             }
-            Some(Token::Append) => {
-                match Parser::collect_single(iter) {
-                    (Some(file_name), None) => Ok(Runnable::Append(Append::new(Command::new(items), file_name))),
-                    (None, Some(token)) => Err(format!("Unexpected token {:?}", token)),
-                    (None, None) => Err("Expected token as filename!".to_string()),
-                    (Some(_), Some(_)) => unreachable!()
+            _ => None
+        };
+        let (default_fd, kind) = match self.peek() {
+            Some(&Token::From) => (0, RedirectKind::From),
+            Some(&Token::Insert) => (1, RedirectKind::Insert),
+            Some(&Token::Append) => (1, RedirectKind::Append),
+            Some(&Token::DupInput) => (0, RedirectKind::Dup),
+            Some(&Token::DupOutput) => (1, RedirectKind::Dup),
+            _ => return Ok(None)
+        };
+        self.pos += 1;
+        let target = match self.next() {
+            Some(Token::Str(word)) => word.content.to_string(),
+            Some(token) => return Err(unexpected(&token)),
+            None => return Err(ParseError::Invalid("Expected token as filename!".to_string()))
+        };
+        Ok(Some(Redirect::new(io_number.unwrap_or(default_fd), kind, target)))
+    }
+
+    fn parse_if(&mut self) -> ParseResult<Runnable> {
+        self.expect_reserved(Reserved::If)?;
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        loop {
+            let cond = self.parse_compound_list(&[Reserved::Then])?;
+            self.expect_reserved(Reserved::Then)?;
+            let body = self.parse_compound_list(&[Reserved::Elif, Reserved::Else, Reserved::Fi])?;
+            branches.push((cond, body));
+            match self.next() {
+                Some(Token::Str(ref word)) if word.reserved() == Some(Reserved::Elif) => continue,
+                Some(Token::Str(ref word)) if word.reserved() == Some(Reserved::Else) => {
+                    otherwise = Some(self.parse_compound_list(&[Reserved::Fi])?);
+                    self.expect_reserved(Reserved::Fi)?;
+                    break;
                 }
+                Some(Token::Str(ref word)) if word.reserved() == Some(Reserved::Fi) => break,
+                Some(token) => return Err(unexpected(&token)),
+                None => return Err(incomplete(Reserved::Fi))
             }
-            Some(Token::From) => {
-                Ok(Runnable::From)
+        }
+        Ok(Runnable::If(If::new(branches, otherwise)))
+    }
+
+    fn parse_for(&mut self) -> ParseResult<Runnable> {
+        self.expect_reserved(Reserved::For)?;
+        if let Some(&Token::Arith(ref expr)) = self.peek() {
+            let parts: Vec<String> = expr.split(';').map(|part| part.to_string()).collect();
+            if parts.len() != 3 {
+                return Err(ParseError::Invalid(format!("Expected three expressions in for (({}))", expr)));
             }
-            Some(_) => {
-                Err("Either unimplemented token type, or collect_items not working".to_string())
+            self.pos += 1;
+            let body = self.parse_do_group()?;
+            let mut parts = parts.into_iter();
+            let (init, cond, step) = (parts.next().unwrap(), parts.next().unwrap(), parts.next().unwrap());
+            return Ok(Runnable::ArithFor(ArithFor::new(init, cond, step, body)));
+        }
+        let var = self.expect_word()?.content.to_string();
+        if !is_valid_name(&var) {
+            return Err(ParseError::Invalid(format!("`{}` is not a valid identifier", var)));
+        }
+        self.skip_newlines();
+        let words = if self.peek_reserved() == Some(Reserved::In) {
+            self.pos += 1;
+            let mut words = Vec::new();
+            while let Some(&Token::Str(ref word)) = self.peek() {
+                words.push(word.content.to_string());
+                self.pos += 1;
             }
-            None => {
-                Ok(Runnable::Cmd(Command::new(items)))
+            Some(words)
+        } else {
+            None
+        };
+        let body = self.parse_do_group()?;
+        Ok(Runnable::For(For::new(var, words, body)))
+    }
+
+    fn parse_while(&mut self, until: bool) -> ParseResult<Runnable> {
+        self.expect_reserved(if until { Reserved::Until } else { Reserved::While })?;
+        let cond = self.parse_compound_list(&[Reserved::Do])?;
+        let body = self.parse_do_group()?;
+        Ok(Runnable::While(While::new(cond, body, until)))
+    }
+
+    /// Parses `[;] do body done`, the optional separator is allowed after a for header
+    fn parse_do_group(&mut self) -> ParseResult<Runnable> {
+        match self.peek() {
+            Some(&Token::Semi) | Some(&Token::NewLine) => self.pos += 1,
+            _ => {}
+        }
+        self.skip_newlines();
+        self.expect_reserved(Reserved::Do)?;
+        let body = self.parse_compound_list(&[Reserved::Done])?;
+        self.expect_reserved(Reserved::Done)?;
+        Ok(body)
+    }
+
+    fn parse_case(&mut self) -> ParseResult<Runnable> {
+        self.expect_reserved(Reserved::Case)?;
+        let word = self.expect_word()?.content.to_string();
+        self.skip_newlines();
+        self.expect_reserved(Reserved::In)?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            match self.peek_reserved() {
+                Some(Reserved::Esac) => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => items.push(self.parse_case_item()?)
             }
         }
+        Ok(Runnable::Case(Case::new(word, items)))
     }
-}
\ No newline at end of file
+
+    fn parse_case_item(&mut self) -> ParseResult<CaseItem> {
+        if let Some(&Token::LParen) = self.peek() {
+            self.pos += 1;
+        }
+        let mut patterns = vec![self.expect_word()?.content.to_string()];
+        loop {
+            match self.next() {
+                Some(Token::Pipe) => patterns.push(self.expect_word()?.content.to_string()),
+                Some(Token::RParen) => break,
+                Some(token) => return Err(unexpected(&token)),
+                None => return Err(ParseError::Incomplete("Expected )".to_string()))
+            }
+        }
+        let body = self.parse_list(&[Reserved::Esac])?;
+        match self.peek() {
+            Some(&Token::DoubleSemi) => self.pos += 1,
+            _ if self.peek_reserved() == Some(Reserved::Esac) => {}
+            Some(token) => return Err(unexpected(token)),
+            None => return Err(incomplete(Reserved::Esac))
+        }
+        Ok(CaseItem { patterns, body })
+    }
+}
+
+fn unexpected(token: &Token) -> ParseError {
+    ParseError::Invalid(format!("Unexpected token {}", token))
+}
+
+fn incomplete(expected: Reserved) -> ParseError {
+    ParseError::Incomplete(format!("Expected {}", expected))
+}
+
+fn is_valid_function_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|c| "$'\"\\`=/".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::{parse, ParseError};
+    use runner::command::*;
+
+    fn is_incomplete(line: &str) -> bool {
+        match parse(line) {
+            Err(ParseError::Incomplete(_)) => true,
+            Err(ParseError::Invalid(_)) => false,
+            Ok(_) => panic!("Expected an error for {}", line)
+        }
+    }
+
+    fn is_invalid(line: &str) -> bool {
+        match parse(line) {
+            Err(ParseError::Invalid(_)) => true,
+            Err(ParseError::Incomplete(_)) => false,
+            Ok(_) => panic!("Expected an error for {}", line)
+        }
+    }
+
+    fn args(runnable: &Runnable) -> Vec<&str> {
+        match *runnable {
+            Runnable::Cmd(ref cmd) => cmd.args.iter().map(|arg| arg.as_str()).collect(),
+            _ => panic!("Expected a simple command")
+        }
+    }
+
+    #[test]
+    fn if_branches() {
+        match parse("if a; then b; elif c\nthen d; else e; fi").unwrap() {
+            Runnable::If(ref cmd) => {
+                assert_eq!(cmd.branches.len(), 2);
+                assert_eq!(args(&cmd.branches[0].0), vec!["a"]);
+                assert_eq!(args(&cmd.branches[0].1), vec!["b"]);
+                assert_eq!(args(&cmd.branches[1].0), vec!["c"]);
+                assert_eq!(args(&cmd.branches[1].1), vec!["d"]);
+                assert_eq!(args(cmd.otherwise.as_ref().unwrap()), vec!["e"]);
+            }
+            _ => panic!("Expected an if command")
+        }
+        assert!(is_incomplete("if a"));
+        assert!(is_incomplete("if a; then b"));
+        assert!(is_incomplete("if a; then b; else c"));
+        assert!(is_invalid("if a; fi"));
+        assert!(is_invalid("if a; then fi"));
+        assert!(is_invalid("if a; then b; fi fi"));
+    }
+
+    #[test]
+    fn case_items() {
+        match parse("case $x in\na|b) one;;\n(c) two; three;;\n*) ;;\nesac").unwrap() {
+            Runnable::Case(ref cmd) => {
+                assert_eq!(cmd.word, "$x");
+                assert_eq!(cmd.items.len(), 3);
+                assert_eq!(cmd.items[0].patterns, vec!["a", "b"]);
+                assert_eq!(args(&cmd.items[0].body), vec!["one"]);
+                assert_eq!(cmd.items[1].patterns, vec!["c"]);
+                match cmd.items[1].body {
+                    Runnable::List(ref items) => assert_eq!(items.len(), 2),
+                    _ => panic!("Expected a list")
+                }
+                assert_eq!(cmd.items[2].patterns, vec!["*"]);
+            }
+            _ => panic!("Expected a case command")
+        }
+        // The last item does not need `;;`
+        match parse("case x in x) a\nesac").unwrap() {
+            Runnable::Case(ref cmd) => assert_eq!(args(&cmd.items[0].body), vec!["a"]),
+            _ => panic!("Expected a case command")
+        }
+        assert!(is_incomplete("case x"));
+        assert!(is_incomplete("case x in"));
+        assert!(is_incomplete("case x in a|"));
+        assert!(is_incomplete("case x in a) b;;"));
+        assert!(is_invalid("case x in a) b; c) d;; esac"));
+        assert!(is_invalid("case x of a) b;; esac"));
+    }
+
+    #[test]
+    fn for_loops() {
+        match parse("for i in a b\ndo echo $i; done").unwrap() {
+            Runnable::For(ref cmd) => {
+                assert_eq!(cmd.var, "i");
+                assert_eq!(cmd.words, Some(vec!["a".to_string(), "b".to_string()]));
+                assert_eq!(args(&cmd.body), vec!["echo", "$i"]);
+            }
+            _ => panic!("Expected a for loop")
+        }
+        // Without `in`, the positional parameters are used
+        match parse("for i; do a; done").unwrap() {
+            Runnable::For(ref cmd) => assert!(cmd.words.is_none()),
+            _ => panic!("Expected a for loop")
+        }
+        match parse("for ((i = 0; i < 3; i++)); do a; done").unwrap() {
+            Runnable::ArithFor(ref cmd) => {
+                assert_eq!(cmd.init.trim(), "i = 0");
+                assert_eq!(cmd.cond.trim(), "i < 3");
+                assert_eq!(cmd.step.trim(), "i++");
+            }
+            _ => panic!("Expected an arithmetic for loop")
+        }
+        assert!(is_incomplete("for i in a b"));
+        assert!(is_incomplete("for i in a b; do echo $i"));
+        assert!(is_invalid("for 1i in a; do b; done"));
+        assert!(is_invalid("for ((i = 0; i < 3)); do a; done"));
+        assert!(is_invalid("for i in a; do done"));
+    }
+
+    #[test]
+    fn while_loops() {
+        match parse("while a; b; do c; done").unwrap() {
+            Runnable::While(ref cmd) => {
+                assert!(!cmd.until);
+                match *cmd.cond {
+                    Runnable::List(ref items) => assert_eq!(items.len(), 2),
+                    _ => panic!("Expected a list")
+                }
+                assert_eq!(args(&cmd.body), vec!["c"]);
+            }
+            _ => panic!("Expected a while loop")
+        }
+        match parse("until a; do b; done").unwrap() {
+            Runnable::While(ref cmd) => assert!(cmd.until),
+            _ => panic!("Expected an until loop")
+        }
+        assert!(is_incomplete("while a"));
+        assert!(is_incomplete("until a; do b"));
+        assert!(is_invalid("while a; done"));
+        assert!(is_invalid("while do b; done"));
+    }
+
+    #[test]
+    fn loop_redirections() {
+        match parse("while read line; do echo $line; done < file").unwrap() {
+            Runnable::Redirected(ref cmd) => {
+                assert_eq!(cmd.redirects.len(), 1);
+                assert_eq!(cmd.redirects[0].fd, 0);
+                assert_eq!(cmd.redirects[0].kind, RedirectKind::From);
+                assert_eq!(cmd.redirects[0].target, "file");
+                match *cmd.cmd {
+                    Runnable::While(_) => {}
+                    _ => panic!("Expected a while loop")
+                }
+            }
+            _ => panic!("Expected a redirected command")
+        }
+        assert!(is_invalid("for i in a; do b; done <"));
+    }
+
+    #[test]
+    fn nested_break() {
+        let outer = match parse("for i in a; do while b; do break 2; done; done").unwrap() {
+            Runnable::For(cmd) => cmd.body,
+            _ => panic!("Expected a for loop")
+        };
+        match *outer {
+            Runnable::While(ref cmd) => assert_eq!(args(&cmd.body), vec!["break", "2"]),
+            _ => panic!("Expected a while loop")
+        }
+    }
+
+    #[test]
+    fn function_definitions() {
+        for line in &["f() { a; }", "function f { a; }", "function f() { a; }", "f()\n{\na\n}"] {
+            match parse(line).unwrap() {
+                Runnable::Function(ref def) => {
+                    assert_eq!(def.name, "f");
+                    match *def.body {
+                        Runnable::Group(ref body) => assert_eq!(args(body), vec!["a"]),
+                        _ => panic!("Expected a group")
+                    }
+                }
+                _ => panic!("Expected a function definition")
+            }
+        }
+        // Any compound command can be the body, redirections are kept with it
+        match parse("f() ( a ) > out").unwrap() {
+            Runnable::Function(ref def) => match *def.body {
+                Runnable::Redirected(ref body) => match *body.cmd {
+                    Runnable::Subshell(_) => {}
+                    _ => panic!("Expected a subshell")
+                },
+                _ => panic!("Expected a redirected command")
+            },
+            _ => panic!("Expected a function definition")
+        }
+        assert!(is_incomplete("f()"));
+        assert!(is_incomplete("f() {"));
+        assert!(is_incomplete("function f { a;"));
+        assert!(is_invalid("f() a"));
+        assert!(is_invalid("f() { }"));
+        assert!(is_invalid("a=b() { c; }"));
+    }
+
+    #[test]
+    fn subshells_and_groups() {
+        match parse("(a; (b))").unwrap() {
+            Runnable::Subshell(ref outer) => match *outer.cmd {
+                Runnable::List(ref items) => {
+                    assert_eq!(args(&items[0]), vec!["a"]);
+                    match items[1] {
+                        Runnable::Subshell(ref inner) => assert_eq!(args(&inner.cmd), vec!["b"]),
+                        _ => panic!("Expected a subshell")
+                    }
+                }
+                _ => panic!("Expected a list")
+            },
+            _ => panic!("Expected a subshell")
+        }
+        match parse("{ a; b; } | c").unwrap() {
+            Runnable::Pipe(ref pipe) => match pipe.commands[0] {
+                Runnable::Group(ref body) => match **body {
+                    Runnable::List(ref items) => assert_eq!(items.len(), 2),
+                    _ => panic!("Expected a list")
+                },
+                _ => panic!("Expected a group")
+            },
+            _ => panic!("Expected a pipe")
+        }
+        assert!(is_incomplete("(a"));
+        assert!(is_incomplete("( a;\nb"));
+        assert!(is_incomplete("{ a;"));
+        assert!(is_invalid("()"));
+        assert!(is_invalid("{ }"));
+        assert!(is_invalid("(a))"));
+        assert!(is_invalid("}"));
+    }
+
+    #[test]
+    fn compound_redirections() {
+        match parse("{ a; } 2>&1 > out").unwrap() {
+            Runnable::Redirected(ref cmd) => {
+                assert_eq!(cmd.redirects.len(), 2);
+                assert_eq!((cmd.redirects[0].fd, cmd.redirects[0].kind), (2, RedirectKind::Dup));
+                assert_eq!(cmd.redirects[0].target, "1");
+                assert_eq!((cmd.redirects[1].fd, cmd.redirects[1].kind), (1, RedirectKind::Insert));
+                match *cmd.cmd {
+                    Runnable::Group(_) => {}
+                    _ => panic!("Expected a group")
+                }
+            }
+            _ => panic!("Expected a redirected command")
+        }
+        match parse("(a) >> log").unwrap() {
+            Runnable::Redirected(ref cmd) => {
+                assert_eq!(cmd.redirects[0].kind, RedirectKind::Append);
+                match *cmd.cmd {
+                    Runnable::Subshell(_) => {}
+                    _ => panic!("Expected a subshell")
+                }
+            }
+            _ => panic!("Expected a redirected command")
+        }
+        assert!(is_invalid("(a) > (b)"));
+    }
+}
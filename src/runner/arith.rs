@@ -0,0 +1,465 @@
+/*
+ * Project: rusth
+ * File: runner/arith.rs
+ * Copyright 2018 Caner Korkmaz (Kausta) <info@canerkorkmaz.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use super::shell::Shell;
+
+#[derive(Debug, Clone, PartialEq)]
+enum ArithToken {
+    Num(i64),
+    Var(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+/// Operators, longer ones first so they are matched before their prefixes
+static OPERATORS: &'static [&'static str] = &[
+    "<<=", ">>=", "**",
+    "++", "--", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=",
+    "==", "!=", "<=", ">=", "&&", "||", "<<", ">>",
+    "+", "-", "*", "/", "%", "<", ">", "!", "=", "~", "&", "|", "^", "?", ":", ",",
+];
+
+/// Evaluates an integer arithmetic expression, variables are read from and assigned to the shell
+pub fn eval(shell: &mut Shell, expr: &str) -> Result<i64, String> {
+    eval_nested(shell, expr, 0)
+}
+
+fn eval_nested(shell: &mut Shell, expr: &str, depth: u32) -> Result<i64, String> {
+    let tokens = tokenize(expr)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut evaluator = Evaluator {
+        shell,
+        tokens,
+        pos: 0,
+        depth,
+    };
+    let value = evaluator.expr(true)?;
+    match evaluator.tokens.get(evaluator.pos) {
+        None => Ok(value),
+        Some(token) => Err(format!("{}: syntax error in expression (error token is {:?})", expr, token))
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<ArithToken>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '#' && c != '@' && c != '_')
+                .unwrap_or_else(|| rest.len());
+            tokens.push(ArithToken::Num(parse_number(&rest[..end])?));
+            rest = &rest[end..];
+        } else if c == '_' || c.is_ascii_alphabetic() {
+            let end = rest.find(|c: char| c != '_' && !c.is_ascii_alphanumeric()).unwrap_or_else(|| rest.len());
+            tokens.push(ArithToken::Var(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if c == '$' && rest[1..].starts_with(|c: char| c == '_' || c.is_ascii_alphabetic()) {
+            // `$name` means the same thing as `name` in an expression
+            rest = &rest[1..];
+            continue;
+        } else if c == '(' {
+            tokens.push(ArithToken::LParen);
+            rest = &rest[1..];
+        } else if c == ')' {
+            tokens.push(ArithToken::RParen);
+            rest = &rest[1..];
+        } else {
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    tokens.push(ArithToken::Op(op));
+                    rest = &rest[op.len()..];
+                }
+                None => return Err(format!("{}: syntax error: invalid arithmetic operator (error token is \"{}\")", expr, rest))
+            }
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Parses decimal, `0x` hexadecimal, `0` octal and `base#digits` numbers
+pub fn parse_number(s: &str) -> Result<i64, String> {
+    let (base, digits) = if let Some(idx) = s.find('#') {
+        match s[..idx].parse::<u32>() {
+            Ok(base) if base >= 2 && base <= 64 => (base, &s[idx + 1..]),
+            _ => return Err(format!("{}: invalid arithmetic base", s))
+        }
+    } else if s.starts_with("0x") || s.starts_with("0X") {
+        (16, &s[2..])
+    } else if s.len() > 1 && s.starts_with('0') {
+        (8, &s[1..])
+    } else {
+        (10, s)
+    };
+    if digits.is_empty() {
+        return Err(format!("{}: invalid number", s));
+    }
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            // Upper case letters are the same as lower case ones up to base 36
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(format!("{}: invalid number", s))
+        };
+        if digit >= base {
+            return Err(format!("{}: value too great for base", s));
+        }
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+    Ok(value)
+}
+
+struct Evaluator<'s> {
+    shell: &'s mut Shell,
+    tokens: Vec<ArithToken>,
+    pos: usize,
+    /// Nesting of variables whose values are evaluated as expressions
+    depth: u32,
+}
+
+// Every level takes `eval`, false when the result is not used because of short circuiting,
+// so that assignments in the skipped part are not done
+impl<'s> Evaluator<'s> {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(&ArithToken::Op(op)) => Some(op),
+            _ => None
+        }
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Comma separated expressions, the value of the last one is used
+    fn expr(&mut self, eval: bool) -> Result<i64, String> {
+        let mut value = self.assignment(eval)?;
+        while self.eat(",") {
+            value = self.assignment(eval)?;
+        }
+        Ok(value)
+    }
+
+    fn assignment(&mut self, eval: bool) -> Result<i64, String> {
+        if let Some(&ArithToken::Var(ref name)) = self.tokens.get(self.pos) {
+            if let Some(&ArithToken::Op(op)) = self.tokens.get(self.pos + 1) {
+                if op.ends_with('=') && !["==", "!=", "<=", ">="].contains(&op) {
+                    let name = name.clone();
+                    self.pos += 2;
+                    let rhs = self.assignment(eval)?;
+                    if !eval {
+                        return Ok(0);
+                    }
+                    let value = if op == "=" {
+                        rhs
+                    } else {
+                        let lhs = self.var_value(&name)?;
+                        binary(&op[..op.len() - 1], lhs, rhs)?
+                    };
+                    self.shell.vars.set(&name, value.to_string())?;
+                    return Ok(value);
+                }
+            }
+        }
+        self.ternary(eval)
+    }
+
+    fn ternary(&mut self, eval: bool) -> Result<i64, String> {
+        let cond = self.logical_or(eval)?;
+        if !self.eat("?") {
+            return Ok(cond);
+        }
+        let then = self.expr(eval && cond != 0)?;
+        if !self.eat(":") {
+            return Err("`:' expected for conditional expression".to_string());
+        }
+        let otherwise = self.ternary(eval && cond == 0)?;
+        Ok(if cond != 0 { then } else { otherwise })
+    }
+
+    fn logical_or(&mut self, eval: bool) -> Result<i64, String> {
+        let mut lhs = self.logical_and(eval)?;
+        while self.eat("||") {
+            let rhs = self.logical_and(eval && lhs == 0)?;
+            lhs = (lhs != 0 || rhs != 0) as i64;
+        }
+        Ok(lhs)
+    }
+
+    fn logical_and(&mut self, eval: bool) -> Result<i64, String> {
+        let mut lhs = self.binary_level(0, eval)?;
+        while self.eat("&&") {
+            let rhs = self.binary_level(0, eval && lhs != 0)?;
+            lhs = (lhs != 0 && rhs != 0) as i64;
+        }
+        Ok(lhs)
+    }
+
+    /// Left associative binary operators, from the lowest precedence to the highest
+    fn binary_level(&mut self, level: usize, eval: bool) -> Result<i64, String> {
+        static LEVELS: &'static [&'static [&'static str]] = &[
+            &["|"],
+            &["^"],
+            &["&"],
+            &["==", "!="],
+            &["<", "<=", ">", ">="],
+            &["<<", ">>"],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+        if level == LEVELS.len() {
+            return self.power(eval);
+        }
+        let mut lhs = self.binary_level(level + 1, eval)?;
+        while let Some(op) = self.peek_op().filter(|op| LEVELS[level].contains(op)) {
+            self.pos += 1;
+            let rhs = self.binary_level(level + 1, eval)?;
+            lhs = if eval { binary(op, lhs, rhs)? } else { 0 };
+        }
+        Ok(lhs)
+    }
+
+    /// `**` is right associative and binds tighter than the other binary operators
+    fn power(&mut self, eval: bool) -> Result<i64, String> {
+        let base = self.unary(eval)?;
+        if !self.eat("**") {
+            return Ok(base);
+        }
+        let exp = self.power(eval)?;
+        if eval {
+            binary("**", base, exp)
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn unary(&mut self, eval: bool) -> Result<i64, String> {
+        match self.peek_op() {
+            Some("~") => {
+                self.pos += 1;
+                Ok(!self.unary(eval)?)
+            }
+            Some("!") => {
+                self.pos += 1;
+                Ok((self.unary(eval)? == 0) as i64)
+            }
+            Some("-") => {
+                self.pos += 1;
+                Ok(self.unary(eval)?.wrapping_neg())
+            }
+            Some("+") => {
+                self.pos += 1;
+                self.unary(eval)
+            }
+            Some(op @ "++") | Some(op @ "--") => {
+                self.pos += 1;
+                let name = match self.tokens.get(self.pos) {
+                    Some(&ArithToken::Var(ref name)) => name.clone(),
+                    _ => return Err(format!("{}: expected a variable", op))
+                };
+                self.pos += 1;
+                let value = step(op, self.var_value(&name)?);
+                if eval {
+                    self.shell.vars.set(&name, value.to_string())?;
+                }
+                Ok(value)
+            }
+            _ => self.postfix(eval)
+        }
+    }
+
+    fn postfix(&mut self, eval: bool) -> Result<i64, String> {
+        if let Some(&ArithToken::Var(ref name)) = self.tokens.get(self.pos) {
+            let name = name.clone();
+            self.pos += 1;
+            let value = self.var_value(&name)?;
+            if let Some(op) = self.peek_op().filter(|op| *op == "++" || *op == "--") {
+                self.pos += 1;
+                if eval {
+                    let new_value = step(op, value);
+                    self.shell.vars.set(&name, new_value.to_string())?;
+                }
+            }
+            return Ok(value);
+        }
+        self.primary(eval)
+    }
+
+    fn primary(&mut self, eval: bool) -> Result<i64, String> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(ArithToken::Num(n)) => {
+                self.pos += 1;
+                Ok(n)
+            }
+            Some(ArithToken::LParen) => {
+                self.pos += 1;
+                let value = self.expr(eval)?;
+                match self.tokens.get(self.pos) {
+                    Some(&ArithToken::RParen) => {
+                        self.pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err("missing `)'".to_string())
+                }
+            }
+            Some(token) => Err(format!("syntax error: operand expected (error token is {:?})", token)),
+            None => Err("syntax error: operand expected".to_string())
+        }
+    }
+
+    fn var_value(&mut self, name: &str) -> Result<i64, String> {
+        let value = match self.shell.vars.get(name) {
+            Some(value) => value.trim().to_string(),
+            None => return Ok(0)
+        };
+        if value.is_empty() {
+            return Ok(0);
+        }
+        if let Ok(n) = value.parse() {
+            return Ok(n);
+        }
+        if self.depth > 32 {
+            return Err(format!("{}: expression recursion level exceeded", name));
+        }
+        eval_nested(self.shell, &value, self.depth + 1)
+    }
+}
+
+/// Applies `++` or `--` to the value, wrapping around like the other operators
+fn step(op: &str, value: i64) -> i64 {
+    if op == "++" {
+        value.wrapping_add(1)
+    } else {
+        value.wrapping_sub(1)
+    }
+}
+
+fn binary(op: &str, lhs: i64, rhs: i64) -> Result<i64, String> {
+    Ok(match op {
+        "+" => lhs.wrapping_add(rhs),
+        "-" => lhs.wrapping_sub(rhs),
+        "*" => lhs.wrapping_mul(rhs),
+        "/" | "%" if rhs == 0 => return Err("division by 0".to_string()),
+        "/" => lhs.wrapping_div(rhs),
+        "%" => lhs.wrapping_rem(rhs),
+        "**" if rhs < 0 => return Err("exponent less than 0".to_string()),
+        "**" => lhs.wrapping_pow(rhs as u32),
+        "<<" => lhs.wrapping_shl(rhs as u32),
+        ">>" => lhs.wrapping_shr(rhs as u32),
+        "&" => lhs & rhs,
+        "|" => lhs | rhs,
+        "^" => lhs ^ rhs,
+        "==" => (lhs == rhs) as i64,
+        "!=" => (lhs != rhs) as i64,
+        "<" => (lhs < rhs) as i64,
+        "<=" => (lhs <= rhs) as i64,
+        ">" => (lhs > rhs) as i64,
+        ">=" => (lhs >= rhs) as i64,
+        _ => return Err(format!("{}: unknown operator", op))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_str(expr: &str) -> Result<i64, String> {
+        eval(&mut Shell::new(), expr)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval_str("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval_str("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval_str("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval_str("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(eval_str("-2 ** 2"), Ok(4));
+        assert_eq!(eval_str("1 | 6 ^ 3 & 5"), Ok(1 | (6 ^ (3 & 5))));
+        assert_eq!(eval_str("1 << 2 + 1"), Ok(8));
+        assert_eq!(eval_str("1 + 1 == 2 && 3 < 4"), Ok(1));
+        assert_eq!(eval_str("0 || 2 > 1 && 0"), Ok(0));
+        assert_eq!(eval_str("!0 + ~0"), Ok(0));
+        assert_eq!(eval_str("0 ? 2 : 1 ? 3 : 4"), Ok(3));
+        assert_eq!(eval_str("1, 2, 3"), Ok(3));
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(eval_str("0x1f"), Ok(31));
+        assert_eq!(eval_str("017"), Ok(15));
+        assert_eq!(eval_str("2#101"), Ok(5));
+        assert_eq!(eval_str("64#_"), Ok(63));
+        assert_eq!(eval_str("36#Z"), Ok(35));
+    }
+
+    #[test]
+    fn short_circuit() {
+        let mut shell = Shell::new();
+        assert_eq!(eval(&mut shell, "0 && (skipped = 1)"), Ok(0));
+        assert_eq!(eval(&mut shell, "1 || (skipped = 1)"), Ok(1));
+        assert_eq!(eval(&mut shell, "1 ? 2 : (skipped = 1)"), Ok(2));
+        assert_eq!(eval(&mut shell, "0 && skipped++"), Ok(0));
+        assert_eq!(shell.vars.get("skipped"), None);
+        assert_eq!(eval(&mut shell, "1 && (assigned = 5)"), Ok(1));
+        assert_eq!(shell.vars.get("assigned"), Some("5"));
+    }
+
+    #[test]
+    fn assignments() {
+        let mut shell = Shell::new();
+        assert_eq!(eval(&mut shell, "counter = 5, counter += 2, counter *= 3"), Ok(21));
+        assert_eq!(eval(&mut shell, "counter++"), Ok(21));
+        assert_eq!(eval(&mut shell, "++counter"), Ok(23));
+        assert_eq!(eval(&mut shell, "counter--"), Ok(23));
+        assert_eq!(eval(&mut shell, "--counter"), Ok(21));
+        assert_eq!(eval(&mut shell, "counter <<= 1"), Ok(42));
+        assert_eq!(shell.vars.get("counter"), Some("42"));
+    }
+
+    #[test]
+    fn overflow_wraps() {
+        let mut shell = Shell::new();
+        assert_eq!(eval(&mut shell, "9223372036854775807 + 1"), Ok(i64::MIN));
+        assert_eq!(eval(&mut shell, "-9223372036854775808 - 1"), Ok(i64::MAX));
+        assert_eq!(eval(&mut shell, "-9223372036854775808 / -1"), Ok(i64::MIN));
+        assert_eq!(eval(&mut shell, "big = 9223372036854775807, big++"), Ok(i64::MAX));
+        assert_eq!(shell.vars.get("big"), Some("-9223372036854775808"));
+        assert_eq!(eval(&mut shell, "--big"), Ok(i64::MAX));
+        assert_eq!(eval(&mut shell, "++big"), Ok(i64::MIN));
+    }
+
+    #[test]
+    fn malformed() {
+        for expr in &["1 +", "(1 + 2", "1 2", "1 ? 2", "3 @ 4", "++5", "08", "2#2", "65#1", "1 / 0", "1 % 0", "2 ** -1"] {
+            assert!(eval_str(expr).is_err(), "{} should fail", expr);
+        }
+    }
+}
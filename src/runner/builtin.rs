@@ -17,9 +17,26 @@
 */
 #[cfg(windows)]
 use super::windows;
-use super::command::{Command, Method};
+use super::arith;
+use super::dirs;
+use super::printf;
+use super::read;
+use super::test;
+use super::command::{Assignment, Command, Method, RunConfig, RunnableCmd};
+use super::executable::{environment, exec_process, find_executable, find_in_path, is_executable, not_started_status, run_process};
+use super::shell::{Shell, Control};
+use super::vars::{is_valid_name, quote_value, Value, Variable, Variables};
 
-use std::env::{set_current_dir, current_dir, home_dir};
+use util::frecency;
+
+use std::collections::BTreeMap;
+use std::env::current_dir;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::path::PathBuf;
+
+use parser::{self, ParseError};
 
 #[cfg(not(windows))]
 fn get_builtin_os(_cmd_name: &str) -> Option<Method> {
@@ -38,52 +55,143 @@ pub fn get_builtin(cmd_name: &str) -> Option<Method> {
         "echo" => Some(echo),
         "cd" => Some(cd),
         "pwd" => Some(pwd),
+        "break" => Some(break_loop),
+        "continue" => Some(continue_loop),
+        "return" => Some(return_function),
+        "local" => Some(local),
+        "trap" => Some(trap),
+        "let" => Some(let_builtin),
+        "declare" => Some(declare),
+        "export" => Some(export),
+        "readonly" => Some(readonly),
+        "unset" => Some(unset),
+        "env" => Some(env),
+        "source" | "." => Some(source),
+        "eval" => Some(eval),
+        "exec" => Some(exec),
+        "command" => Some(command),
+        "builtin" => Some(builtin),
+        "exit" => Some(exit),
+        "pushd" => Some(pushd),
+        "popd" => Some(popd),
+        "dirs" => Some(dirs_builtin),
+        "z" | "j" => Some(jump),
+        "set" => Some(set),
+        "printf" => Some(printf_builtin),
+        "read" => Some(read::read),
+        "test" | "[" => Some(test::test),
+        "logout" => Some(logout),
         _ => None
     }
 }
 
-pub fn echo(cmd: &Command) -> Option<i32> {
-    for s in cmd.args.iter().skip(1) {
-        print!("{} ", s);
+/// Prints the arguments separated by spaces, `-n` leaves out the newline
+/// and `-e` replaces backslash escapes, which `-E` turns off again
+pub fn echo(_shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let mut newline = true;
+    let mut escapes = false;
+    let mut args = cmd.args.iter().skip(1).peekable();
+    while let Some(arg) = args.peek().cloned() {
+        // Only words made of option letters are options, anything else is printed
+        if arg.len() < 2 || !arg.starts_with('-') || !arg[1..].chars().all(|c| "neE".contains(c)) {
+            break;
+        }
+        for c in arg[1..].chars() {
+            match c {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false
+            }
+        }
+        args.next();
+    }
+    let text = args.map(|arg| arg.as_str()).collect::<Vec<_>>().join(" ");
+    let mut output = if escapes {
+        let (unescaped, stop) = printf::echo_escapes(&text);
+        newline &= !stop;
+        unescaped
+    } else {
+        text.into_bytes()
+    };
+    if newline {
+        output.push(b'\n');
     }
-    println!();
+    let _ = io::stdout().write_all(&output);
     Some(0)
 }
 
-pub fn cd(cmd: &Command) -> Option<i32> {
-    let c = cmd.args.len();
-    if c < 2 {
-        cd_impl("~")
-    } else {
-        cd_impl(cmd.args[1].as_ref())
+pub fn cd(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let mut physical = false;
+    let mut args = cmd.args.iter().skip(1).peekable();
+    while let Some(arg) = args.peek().cloned() {
+        match arg.as_str() {
+            "-L" => physical = false,
+            "-P" => physical = true,
+            "--" => {
+                args.next();
+                break;
+            }
+            _ => break
+        }
+        args.next();
     }
+    let dir = match args.next() {
+        Some(dir) if dir == "-" => match shell.vars.get("OLDPWD").map(|old| old.to_string()) {
+            // `cd -` prints the directory it goes back to
+            Some(old) => return cd_impl(shell, &old, physical, true),
+            None => {
+                eprintln!("cd: OLDPWD not set");
+                return Some(1);
+            }
+        },
+        Some(dir) => dir.clone(),
+        None => match shell.vars.get("HOME") {
+            Some(home) => home.to_string(),
+            None => {
+                eprintln!("cd: HOME not set");
+                return Some(1);
+            }
+        }
+    };
+    cd_impl(shell, &dir, physical, false)
 }
 
-fn cd_impl(dir: &str) -> Option<i32> {
-    use std::path::PathBuf;
-
-    let dir = if dir.starts_with('~') {
-        let dir = dir.replacen("~", "", 1);
-
-        let home_dir = home_dir();
-        if home_dir.is_none() {
-            eprintln!("Cannot get home directory!");
-            return Some(5);
-        }
-        let home_dir = home_dir.unwrap();
-        home_dir.join(dir)
+/// Changes to `dir`, which is already expanded, so a `~` in it is a literal directory name
+fn cd_impl(shell: &mut Shell, dir: &str, physical: bool, mut print: bool) -> Option<i32> {
+    let dir = if let Some(found) = dirs::search_cdpath(&shell.vars, dir) {
+        // Directories found through CDPATH are printed, since they may not be the ones expected
+        print = true;
+        found
     } else {
         PathBuf::from(dir)
     };
-    let res = set_current_dir(dir);
+    let res = dirs::change(&mut shell.vars, &dir, physical);
     if let Err(e) = res {
         eprintln!("Cannot change directory: {0}", e);
-        return Some(2);
+        if !dir.exists() {
+            if let Some(suggestion) = dirs::correct_spelling(&dir) {
+                eprintln!("Did you mean {}?", suggestion.display());
+            }
+        }
+        return Some(1);
+    }
+    // Only the directories the user visits are ranked, not the ones of scripts.
+    // A database that cannot be written should not make `cd` fail.
+    if shell.interactive {
+        let _ = frecency::record(&dirs::current(&shell.vars).display().to_string());
+    }
+    if print {
+        println!("{}", dirs::current(&shell.vars).display());
     }
     Some(0)
 }
 
-pub fn pwd(_cmd: &Command) -> Option<i32> {
+pub fn pwd(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    // The logical directory keeps the symbolic links followed by `cd`, unless `-P` is given
+    if cmd.args.iter().skip(1).all(|arg| arg != "-P") {
+        println!("{}", dirs::current(&shell.vars).display());
+        return Some(0);
+    }
     let cd = current_dir();
     match cd {
         Ok(dir) => {
@@ -95,4 +203,846 @@ pub fn pwd(_cmd: &Command) -> Option<i32> {
             Some(1)
         }
     }
-}
\ No newline at end of file
+}
+
+pub fn break_loop(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    loop_control(shell, cmd, Control::Break)
+}
+
+pub fn continue_loop(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    loop_control(shell, cmd, Control::Continue)
+}
+
+fn loop_control(shell: &mut Shell, cmd: &Command, control: fn(u32) -> Control) -> Option<i32> {
+    let count = match cmd.args.get(1) {
+        Some(arg) => match arg.parse::<u32>() {
+            Ok(count) if count > 0 => count,
+            _ => {
+                eprintln!("{}: {}: loop count out of range", cmd.command(), arg);
+                return Some(1);
+            }
+        },
+        None => 1
+    };
+    if shell.loop_depth == 0 {
+        eprintln!("{}: only meaningful in a loop", cmd.command());
+        return Some(0);
+    }
+    shell.control = Some(control(count.min(shell.loop_depth)));
+    Some(0)
+}
+
+pub fn return_function(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    if shell.function_depth == 0 && shell.source_depth == 0 {
+        eprintln!("return: can only `return' from a function or sourced script");
+        return Some(1);
+    }
+    let code = match cmd.args.get(1) {
+        Some(arg) => match arg.parse::<i32>() {
+            Ok(code) => Some(code & 0xff),
+            Err(_) => {
+                eprintln!("return: {}: numeric argument required", arg);
+                Some(2)
+            }
+        },
+        None => shell.last_status
+    };
+    shell.control = Some(Control::Return(code));
+    code
+}
+
+pub fn local(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    if shell.function_depth == 0 {
+        eprintln!("local: can only be used in a function");
+        return Some(1);
+    }
+    let mut code = Some(0);
+    for arg in cmd.args.iter().skip(1) {
+        let assignment = Assignment::parse(arg);
+        let name = assignment.as_ref().map_or(arg.as_str(), |assignment| assignment.name.as_str());
+        if !is_valid_name(name) {
+            eprintln!("local: `{}': not a valid identifier", arg);
+            code = Some(1);
+            continue;
+        }
+        shell.vars.make_local(name);
+        let res = match assignment {
+            Some(ref assignment) => assignment.assign(shell),
+            None => shell.vars.unset(name)
+        };
+        if let Err(e) = res {
+            eprintln!("local: {}", e);
+            code = Some(1);
+        }
+    }
+    code
+}
+
+pub fn trap(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let mut args: Vec<&str> = cmd.args.iter().skip(1).map(|arg| arg.as_str()).collect();
+    if args.first() == Some(&"--") {
+        args.remove(0);
+    }
+    if args.is_empty() || args == ["-p"] {
+        let mut traps: Vec<_> = shell.traps.iter().collect();
+        traps.sort();
+        for (condition, action) in traps {
+            println!("trap -- '{}' {}", action.replace('\'', "'\\''"), condition);
+        }
+        return Some(0);
+    }
+    // A single argument resets the condition, like `-`
+    let (action, conditions) = if args.len() == 1 { ("-", &args[..]) } else { (args[0], &args[1..]) };
+    let mut code = Some(0);
+    for condition in conditions {
+        let condition = match *condition {
+            "EXIT" | "0" => "EXIT",
+            _ => {
+                eprintln!("trap: {}: only EXIT can be trapped", condition);
+                code = Some(1);
+                continue;
+            }
+        };
+        if action == "-" {
+            shell.traps.remove(condition);
+        } else {
+            shell.traps.insert(condition.to_string(), action.to_string());
+        }
+    }
+    code
+}
+
+/// Evaluates each argument as an arithmetic expression, fails if the last one is zero
+pub fn let_builtin(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    if cmd.args.len() < 2 {
+        eprintln!("let: expression expected");
+        return Some(1);
+    }
+    let mut value = 0;
+    for arg in cmd.args.iter().skip(1) {
+        match arith::eval(shell, arg) {
+            Ok(v) => value = v,
+            Err(e) => {
+                eprintln!("let: {}", e);
+                return Some(1);
+            }
+        }
+    }
+    Some(if value == 0 { 1 } else { 0 })
+}
+
+/// Attribute changes requested by the options of `declare`, `export` and `readonly`
+#[derive(Default)]
+struct Attributes {
+    print: bool,
+    exported: Option<bool>,
+    readonly: bool,
+    integer: Option<bool>,
+    indexed: bool,
+    assoc: bool,
+}
+
+impl Attributes {
+    fn matches(&self, var: &Variable) -> bool {
+        self.exported.map_or(true, |exported| var.exported == exported) &&
+            (!self.readonly || var.readonly) &&
+            self.integer.map_or(true, |integer| var.integer == integer) &&
+            (!self.indexed || is_indexed(var)) &&
+            (!self.assoc || is_assoc(var))
+    }
+}
+
+fn is_indexed(var: &Variable) -> bool {
+    match var.value {
+        Some(Value::Indexed(_)) => true,
+        _ => false
+    }
+}
+
+fn is_assoc(var: &Variable) -> bool {
+    match var.value {
+        Some(Value::Assoc(_)) => true,
+        _ => false
+    }
+}
+
+/// Turns the variable into an indexed or an associative array, a scalar value becomes the element at `0`
+fn make_array(name: &str, var: &mut Variable, assoc: bool) -> Result<(), String> {
+    var.value = Some(match var.value.take() {
+        None if assoc => Value::Assoc(BTreeMap::new()),
+        None => Value::Indexed(BTreeMap::new()),
+        Some(Value::Scalar(value)) if assoc => Value::Assoc(vec![("0".to_string(), value)].into_iter().collect()),
+        Some(Value::Scalar(value)) => Value::Indexed(vec![(0, value)].into_iter().collect()),
+        Some(Value::Assoc(elements)) if assoc => Value::Assoc(elements),
+        Some(Value::Indexed(elements)) if !assoc => Value::Indexed(elements),
+        value => {
+            var.value = value;
+            return Err(format!("{}: cannot convert {} array", name, if assoc { "indexed to associative" } else { "associative to indexed" }));
+        }
+    });
+    Ok(())
+}
+
+/// Prints the variable the way `declare` would create it
+fn print_declaration(name: &str, var: &Variable) {
+    let mut flags = String::new();
+    if is_indexed(var) {
+        flags.push('a');
+    }
+    if is_assoc(var) {
+        flags.push('A');
+    }
+    if var.integer {
+        flags.push('i');
+    }
+    if var.readonly {
+        flags.push('r');
+    }
+    if var.exported {
+        flags.push('x');
+    }
+    if flags.is_empty() {
+        flags.push('-');
+    }
+    match var.value {
+        Some(Value::Scalar(ref value)) => println!("declare -{} {}={}", flags, name, quote_value(value)),
+        Some(ref value) => {
+            let elements: Vec<String> = value.keys().into_iter()
+                .zip(value.values())
+                .map(|(key, value)| format!("[{}]={}", key, quote_value(value)))
+                .collect();
+            println!("declare -{} {}=({})", flags, name, elements.join(" "));
+        }
+        None => println!("declare -{} {}", flags, name)
+    }
+}
+
+/// Shared implementation of `declare`, `export` and `readonly`, `options` are the ones
+/// accepted by the builtin and `attrs` are the changes it does without any options
+fn declare_impl(shell: &mut Shell, cmd: &Command, options: &str, mut attrs: Attributes) -> Option<i32> {
+    let name = cmd.command();
+    let mut args = cmd.args.iter().skip(1).peekable();
+    let mut listing = attrs.exported.is_none() && !attrs.readonly;
+    while let Some(arg) = args.peek().cloned() {
+        if arg == "--" {
+            args.next();
+            break;
+        }
+        let on = match arg.chars().next() {
+            Some('-') => true,
+            Some('+') => false,
+            _ => break
+        };
+        if arg.len() == 1 {
+            break;
+        }
+        args.next();
+        for c in arg.chars().skip(1) {
+            if !options.contains(c) {
+                eprintln!("{}: {}{}: invalid option", name, if on { '-' } else { '+' }, c);
+                return Some(2);
+            }
+            match c {
+                'p' => attrs.print = true,
+                'x' => attrs.exported = Some(on),
+                'n' => attrs.exported = Some(false),
+                'i' => attrs.integer = Some(on),
+                'r' if on => attrs.readonly = true,
+                'a' if on => attrs.indexed = true,
+                'A' if on => attrs.assoc = true,
+                _ => {
+                    eprintln!("{}: {}{}: cannot be removed", name, if on { '-' } else { '+' }, c);
+                    return Some(2);
+                }
+            }
+            listing = false;
+        }
+    }
+    let names: Vec<&String> = args.collect();
+    if names.is_empty() {
+        // `declare` with only attributes lists the variables that have them
+        let filter = if listing { Attributes::default() } else { attrs };
+        for (name, var) in shell.vars.sorted() {
+            if filter.matches(var) {
+                print_declaration(name, var);
+            }
+        }
+        return Some(0);
+    }
+    let mut code = Some(0);
+    for arg in names {
+        let assignment = Assignment::parse(arg);
+        let var_name = assignment.as_ref().map_or(arg.as_str(), |assignment| assignment.name.as_str());
+        if !is_valid_name(var_name) {
+            eprintln!("{}: `{}': not a valid identifier", name, arg);
+            code = Some(1);
+            continue;
+        }
+        if attrs.print {
+            match shell.vars.get_var(var_name) {
+                Some(var) => print_declaration(var_name, var),
+                None => {
+                    eprintln!("{}: {}: not found", name, var_name);
+                    code = Some(1);
+                }
+            }
+            continue;
+        }
+        // Like `local`, `declare` creates variables local to the running function
+        if name == "declare" && shell.function_depth > 0 {
+            shell.vars.make_local(var_name);
+        }
+        if let Some(integer) = attrs.integer {
+            shell.vars.declare(var_name).integer = integer;
+        }
+        if attrs.indexed || attrs.assoc {
+            if let Err(e) = make_array(var_name, shell.vars.declare(var_name), attrs.assoc) {
+                eprintln!("{}: {}", name, e);
+                code = Some(1);
+                continue;
+            }
+        }
+        if let Some(ref assignment) = assignment {
+            if let Err(e) = assignment.assign(shell) {
+                eprintln!("{}: {}", name, e);
+                code = Some(1);
+                continue;
+            }
+        }
+        let var = shell.vars.declare(var_name);
+        if let Some(exported) = attrs.exported {
+            var.exported = exported;
+        }
+        if attrs.readonly {
+            var.readonly = true;
+        }
+    }
+    code
+}
+
+pub fn declare(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    declare_impl(shell, cmd, "pxriaA", Attributes::default())
+}
+
+pub fn export(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    declare_impl(shell, cmd, "pn", Attributes {
+        exported: Some(true),
+        ..Attributes::default()
+    })
+}
+
+pub fn readonly(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    declare_impl(shell, cmd, "p", Attributes {
+        readonly: true,
+        ..Attributes::default()
+    })
+}
+
+pub fn unset(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let mut functions = false;
+    let mut variables = false;
+    let mut code = Some(0);
+    for arg in cmd.args.iter().skip(1) {
+        match arg.as_str() {
+            "-f" => functions = true,
+            "-v" => variables = true,
+            name if functions => {
+                shell.functions.remove(name);
+            }
+            name if name.ends_with(']') && name.contains('[') => {
+                let open = name.find('[').unwrap_or_default();
+                let res = if is_valid_name(&name[..open]) {
+                    shell.unset_element(&name[..open], &name[open + 1..name.len() - 1])
+                } else {
+                    Err(format!("`{}': not a valid identifier", name))
+                };
+                if let Err(e) = res {
+                    eprintln!("unset: {}", e);
+                    code = Some(1);
+                }
+            }
+            name if !is_valid_name(name) => {
+                eprintln!("unset: `{}': not a valid identifier", name);
+                code = Some(1);
+            }
+            // Without an option, a function is removed if there is no variable with the name
+            name if !variables && shell.vars.get_var(name).is_none() => {
+                shell.functions.remove(name);
+            }
+            name => {
+                if let Err(e) = shell.vars.unset(name) {
+                    eprintln!("unset: {}", e);
+                    code = Some(1);
+                }
+            }
+        }
+    }
+    code
+}
+
+/// Prints the environment, or runs a command with a changed one
+pub fn env(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let mut args = cmd.args.iter().skip(1).peekable();
+    let mut ignore_env = false;
+    if let Some(&arg) = args.peek() {
+        if arg == "-i" || arg == "-" {
+            ignore_env = true;
+            args.next();
+        }
+    }
+    // Assignments in front of `env` are in its environment like in the one of a process
+    let mut assignments = if ignore_env { Vec::new() } else { cmd.assignments.clone() };
+    while let Some(assignment) = args.peek().and_then(|arg| Assignment::parse(arg)) {
+        assignments.push(assignment);
+        args.next();
+    }
+    let empty = Variables::new();
+    let vars = if ignore_env { &empty } else { &shell.vars };
+    let cmd = Command::new(assignments, args.cloned().collect(), Vec::new());
+    if !cmd.empty() {
+        return run_process(&cmd, vars, RunConfig::default());
+    }
+    let mut env: Vec<_> = environment(&cmd, vars).into_iter().collect();
+    env.sort();
+    for (name, value) in env {
+        println!("{}={}", name, value);
+    }
+    Some(0)
+}
+
+/// Runs the commands of a file in the current shell, with the given positional parameters if there are any
+pub fn source(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let name = match cmd.args.get(1) {
+        Some(name) => name,
+        None => {
+            eprintln!("{}: filename argument required", cmd.args[0]);
+            return Some(2);
+        }
+    };
+    // Names without a slash are looked up in PATH first, then in the current directory
+    let path = if name.contains('/') {
+        PathBuf::from(name)
+    } else {
+        find_in_path(name, &shell.vars).unwrap_or_else(|| PathBuf::from(name))
+    };
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}: {}: {}", cmd.args[0], name, e);
+            return Some(1);
+        }
+    };
+    let positional = if cmd.args.len() > 2 {
+        Some(mem::replace(&mut shell.positional, cmd.args[2..].to_vec()))
+    } else {
+        None
+    };
+    shell.source_depth += 1;
+    let mut code = super::run_lines(shell, name, BufReader::new(file).lines());
+    shell.source_depth -= 1;
+    if let Some(Control::Return(ret)) = shell.control {
+        shell.control = None;
+        code = ret;
+    }
+    if let Some(positional) = positional {
+        shell.positional = positional;
+    }
+    code
+}
+
+/// Joins the arguments and runs them as a command in the current shell
+pub fn eval(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let text = cmd.args[1..].join(" ");
+    if text.trim().is_empty() {
+        return Some(0);
+    }
+    match parser::parse(&text) {
+        Ok(parsed) => parsed.run(shell, RunConfig::default()),
+        Err(ParseError::Incomplete(e)) | Err(ParseError::Invalid(e)) => {
+            eprintln!("eval: {}", e);
+            Some(2)
+        }
+    }
+}
+
+/// Replaces the shell with the command, without one the redirections are kept for the session
+pub fn exec(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    if cmd.args.len() < 2 {
+        return Some(0);
+    }
+    let target = cmd.shifted();
+    let e = exec_process(&target, &shell.vars);
+    eprintln!("exec: {}: {}", target.command(), e);
+    Some(not_started_status(&e))
+}
+
+/// Runs a builtin or a program, skipping functions with the same name,
+/// `-v` and `-V` print what would be run instead
+pub fn command(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    command_with(shell, cmd, RunConfig::default())
+}
+
+/// Same as `command`, the program it runs gets the input and output in `conf`
+pub fn command_with(shell: &mut Shell, cmd: &Command, conf: RunConfig) -> Option<i32> {
+    let mut verbose = None;
+    let mut start = 1;
+    for arg in cmd.args.iter().skip(1) {
+        match arg.as_str() {
+            "--" => {
+                start += 1;
+                break;
+            }
+            "-v" => verbose = Some(false),
+            "-V" => verbose = Some(true),
+            "-p" => {}
+            opt if opt.starts_with('-') && opt.len() > 1 => {
+                eprintln!("command: {}: invalid option", opt);
+                return Some(2);
+            }
+            _ => break
+        }
+        start += 1;
+    }
+    let names = &cmd.args[start..];
+    if names.is_empty() {
+        return Some(0);
+    }
+    if let Some(verbose) = verbose {
+        let mut code = Some(0);
+        for name in names {
+            if !describe(shell, name, verbose) {
+                if verbose {
+                    eprintln!("command: {}: not found", name);
+                }
+                code = Some(1);
+            }
+        }
+        return code;
+    }
+    let target = Command::new(cmd.assignments.clone(), names.to_vec(), Vec::new());
+    match get_builtin(target.command()) {
+        Some(_) if target.command() == "command" => command_with(shell, &target, conf),
+        Some(builtin) => builtin(shell, &target),
+        None => run_process(&target, &shell.vars, conf)
+    }
+}
+
+/// Prints what `name` refers to, only the name or path unless `verbose`, returns false if nothing
+fn describe(shell: &Shell, name: &str, verbose: bool) -> bool {
+    let kind = if parser::is_reserved_word(name) {
+        "a shell keyword"
+    } else if shell.functions.contains_key(name) {
+        "a function"
+    } else if get_builtin(name).is_some() {
+        "a shell builtin"
+    } else {
+        let path = if name.contains('/') {
+            Some(PathBuf::from(name)).filter(|path| is_executable(path))
+        } else {
+            find_executable(name, &shell.vars)
+        };
+        return match path {
+            Some(path) => {
+                if verbose {
+                    println!("{} is {}", name, path.display());
+                } else {
+                    println!("{}", path.display());
+                }
+                true
+            }
+            None => false
+        };
+    };
+    if verbose {
+        println!("{} is {}", name, kind);
+    } else {
+        println!("{}", name);
+    }
+    true
+}
+
+/// Runs the builtin with the given name even if a function has the same name
+pub fn builtin(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let target = match cmd.args.get(1) {
+        Some(_) => cmd.shifted(),
+        None => return Some(0)
+    };
+    match get_builtin(target.command()) {
+        Some(builtin) => builtin(shell, &target),
+        None => {
+            eprintln!("builtin: {}: not a shell builtin", target.command());
+            Some(1)
+        }
+    }
+}
+
+/// Exits the shell with the given status, or the last one, after the exit trap runs.
+/// An interactive shell with running jobs only warns about them the first time.
+pub fn exit(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let code = match cmd.args.len() {
+        1 => shell.last_status,
+        2 => match cmd.args[1].parse::<i32>() {
+            Ok(code) => Some(code & 0xff),
+            Err(_) => {
+                eprintln!("{}: {}: numeric argument required", cmd.args[0], cmd.args[1]);
+                Some(2)
+            }
+        },
+        _ => {
+            eprintln!("{}: too many arguments", cmd.args[0]);
+            return Some(1);
+        }
+    };
+    shell.reap_background();
+    if shell.interactive && !shell.jobs_warned && !shell.background.is_empty() {
+        eprintln!("There are running jobs: {}", shell.background.iter()
+            .map(|pid| pid.to_string())
+            .collect::<Vec<_>>()
+            .join(" "));
+        shell.jobs_warned = true;
+        return Some(1);
+    }
+    shell.control = Some(Control::Exit(code));
+    code
+}
+
+/// `exit` for login shells
+pub fn logout(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    if !shell.login {
+        eprintln!("logout: not login shell: use `exit'");
+        return Some(1);
+    }
+    exit(shell, cmd)
+}
+
+/// Prints the directory stack, with `~` for the home directory unless `long` is set
+fn print_dirs(shell: &Shell, long: bool, per_line: bool, numbered: bool) {
+    let names: Vec<String> = dirs::stack(shell).iter()
+        .map(|dir| if long { dir.display().to_string() } else { dirs::abbreviate(&shell.vars, dir) })
+        .collect();
+    if numbered {
+        for (i, name) in names.iter().enumerate() {
+            println!("{:2}  {}", i, name);
+        }
+    } else if per_line {
+        for name in names.iter() {
+            println!("{}", name);
+        }
+    } else {
+        println!("{}", names.join(" "));
+    }
+}
+
+fn is_stack_index(arg: &str) -> bool {
+    (arg.starts_with('+') || arg.starts_with('-')) && arg.len() > 1
+}
+
+/// Saves the current directory on the stack and changes to the given one,
+/// `+N` and `-N` rotate the stack instead and no argument swaps the top two directories
+pub fn pushd(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let mut stack = dirs::stack(shell);
+    match cmd.args.get(1) {
+        Some(arg) if is_stack_index(arg) => match dirs::stack_index(stack.len(), arg) {
+            Some(i) => stack.rotate_left(i),
+            None => {
+                eprintln!("pushd: {}: directory stack index out of range", arg);
+                return Some(1);
+            }
+        },
+        Some(dir) => {
+            let code = cd_impl(shell, dir, false, false);
+            if code == Some(0) {
+                shell.dir_stack.insert(0, stack.remove(0));
+                print_dirs(shell, false, false, false);
+            }
+            return code;
+        }
+        None => {
+            if stack.len() < 2 {
+                eprintln!("pushd: no other directory");
+                return Some(1);
+            }
+            stack.swap(0, 1);
+        }
+    }
+    let code = cd_impl(shell, &stack[0].display().to_string(), false, false);
+    if code == Some(0) {
+        shell.dir_stack = stack.split_off(1);
+        print_dirs(shell, false, false, false);
+    }
+    code
+}
+
+/// Removes the top directory of the stack and changes to the next one,
+/// `+N` and `-N` remove that entry instead
+pub fn popd(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let stack = dirs::stack(shell);
+    if stack.len() < 2 {
+        eprintln!("popd: directory stack empty");
+        return Some(1);
+    }
+    let i = match cmd.args.get(1) {
+        None => 0,
+        Some(arg) => match dirs::stack_index(stack.len(), arg).filter(|_| is_stack_index(arg)) {
+            Some(i) => i,
+            None => {
+                eprintln!("popd: {}: invalid argument", arg);
+                return Some(1);
+            }
+        }
+    };
+    if i == 0 {
+        let code = cd_impl(shell, &stack[1].display().to_string(), false, false);
+        if code != Some(0) {
+            return code;
+        }
+        shell.dir_stack.remove(0);
+    } else {
+        shell.dir_stack.remove(i - 1);
+    }
+    print_dirs(shell, false, false, false);
+    Some(0)
+}
+
+/// Lists the directory stack, `-v` numbers the entries, `-p` prints one per line,
+/// `-l` shows full paths and `-c` clears the stack
+pub fn dirs_builtin(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let (mut long, mut per_line, mut numbered) = (false, false, false);
+    for arg in cmd.args.iter().skip(1) {
+        if is_stack_index(arg) && arg[1..].chars().all(|c| c.is_ascii_digit()) {
+            let stack = dirs::stack(shell);
+            return match dirs::stack_index(stack.len(), arg) {
+                Some(i) => {
+                    println!("{}", dirs::abbreviate(&shell.vars, &stack[i]));
+                    Some(0)
+                }
+                None => {
+                    eprintln!("dirs: {}: directory stack index out of range", arg);
+                    Some(1)
+                }
+            };
+        }
+        match arg.as_str() {
+            "-c" => {
+                shell.dir_stack.clear();
+                return Some(0);
+            }
+            "-l" => long = true,
+            "-p" => per_line = true,
+            "-v" => numbered = true,
+            _ => {
+                eprintln!("dirs: {}: invalid option", arg);
+                return Some(2);
+            }
+        }
+    }
+    print_dirs(shell, long, per_line, numbered);
+    Some(0)
+}
+
+/// Changes to the most frecent directory visited with `cd` that matches the patterns,
+/// `-l` or no patterns list the matches instead with their scores
+pub fn jump(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let mut list = false;
+    let mut patterns = Vec::new();
+    for arg in cmd.args.iter().skip(1) {
+        if arg == "-l" {
+            list = true;
+        } else {
+            patterns.push(arg.clone());
+        }
+    }
+    let candidates = frecency::candidates(&patterns);
+    if list || patterns.is_empty() {
+        for (score, path) in candidates {
+            println!("{:<10.1} {}", score, path);
+        }
+        return Some(0);
+    }
+    match candidates.last() {
+        Some(&(_, ref path)) => cd_impl(shell, path, false, false),
+        None => {
+            eprintln!("{}: no match for {}", cmd.args[0], patterns.join(" "));
+            Some(1)
+        }
+    }
+}
+
+/// Sets shell options with `-o name` or `-e` and `-x`, `+` unsets them instead,
+/// the arguments after the options replace the positional parameters
+pub fn set(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let mut args = cmd.args.iter().skip(1);
+    let mut positional = None;
+    while let Some(arg) = args.next() {
+        let on = arg.starts_with('-');
+        if arg == "--" || arg == "-" {
+            positional = Some(args.by_ref().cloned().collect());
+            break;
+        }
+        if !on && !arg.starts_with('+') || arg.len() < 2 {
+            positional = Some(::std::iter::once(arg).chain(args.by_ref()).cloned().collect());
+            break;
+        }
+        for c in arg.chars().skip(1) {
+            let res = match c {
+                'e' => shell.options.set("errexit", on),
+                'x' => shell.options.set("xtrace", on),
+                'o' => match args.next() {
+                    Some(name) => shell.options.set(name, on),
+                    None => {
+                        for (name, value) in shell.options.list() {
+                            println!("{:<15} {}", name, if value { "on" } else { "off" });
+                        }
+                        Ok(())
+                    }
+                },
+                _ => Err(format!("-{}: invalid option", c))
+            };
+            if let Err(e) = res {
+                eprintln!("set: {}", e);
+                return Some(2);
+            }
+        }
+    }
+    if let Some(positional) = positional {
+        shell.positional = positional;
+    }
+    Some(0)
+}
+
+/// Formats the arguments like the C function, `-v name` assigns the output to a variable
+pub fn printf_builtin(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let mut args = &cmd.args[1..];
+    let mut var = None;
+    if args.first().map_or(false, |arg| arg == "-v") {
+        match args.get(1) {
+            Some(name) => var = Some(name.clone()),
+            None => {
+                eprintln!("printf: -v: option requires an argument");
+                return Some(2);
+            }
+        }
+        args = &args[2..];
+    }
+    if args.first().map_or(false, |arg| arg == "--") {
+        args = &args[1..];
+    }
+    let format = match args.first() {
+        Some(format) => format,
+        None => {
+            eprintln!("printf: usage: printf [-v var] format [arguments]");
+            return Some(2);
+        }
+    };
+    let (output, valid) = printf::format(format, &args[1..]);
+    match var {
+        Some(name) => if let Err(e) = shell.assign(&name, String::from_utf8_lossy(&output).into_owned()) {
+            eprintln!("printf: {}", e);
+            return Some(1);
+        },
+        None => {
+            let _ = io::stdout().write_all(&output);
+        }
+    }
+    Some(if valid { 0 } else { 1 })
+}
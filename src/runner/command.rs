@@ -16,40 +16,100 @@
  * limitations under the License.
 */
 use std::{
+    fmt,
+    path::Path,
     process::Stdio,
-    borrow::Cow,
-    io::Write,
-    fs::OpenOptions,
+    rc::Rc,
 };
 
 use super::{
-    builtin::get_builtin,
-    executable::{run_process, spawn_process},
+    arith,
+    builtin::{self, get_builtin},
+    executable::{find_executable, run_process},
+    expand::{expand_words, expand_word, expand_arith, expand_pattern, expand_array},
+    job,
+    procsub,
+    redirect,
+    shell::Shell,
+    test,
 };
+#[cfg(not(unix))]
+use super::executable::spawn_process;
+use util::glob;
+use super::vars::is_valid_name;
 
-pub type Method = fn(&Command) -> Option<i32>;
+pub type Method = fn(&mut Shell, &Command) -> Option<i32>;
 
 pub trait RunnableCmd {
-    fn run(&mut self, conf: RunConfig) -> Option<i32>;
+    fn run(&self, shell: &mut Shell, conf: RunConfig) -> Option<i32>;
 }
 
-pub enum Runnable<'a> {
-    Cmd(Command<'a>),
-    Pipe(Pipe<'a>),
-    Insert(Insert<'a>),
-    From,
-    Append(Append<'a>),
+pub enum Runnable {
+    Cmd(Command),
+    Pipe(Pipe),
+    /// `! pipeline`, inverts the status
+    Not(Box<Runnable>),
+    /// Compound command with redirections applied to all of it
+    Redirected(Redirected),
+    /// Commands separated by `;` or new lines, run one after another
+    List(Vec<Runnable>),
+    AndOr(AndOr),
+    /// `{ list; }`, runs in the current shell
+    Group(Box<Runnable>),
+    Subshell(Subshell),
+    /// `cmd &`, runs without being waited for
+    Background(Background),
+    If(If),
+    Case(Case),
+    For(For),
+    ArithFor(ArithFor),
+    Arith(Arith),
+    Cond(Cond),
+    While(While),
+    Function(FunctionDef),
 }
 
-impl<'a> RunnableCmd for Runnable<'a> {
-    fn run(&mut self, conf: RunConfig) -> Option<i32> {
+impl RunnableCmd for Runnable {
+    fn run(&self, shell: &mut Shell, conf: RunConfig) -> Option<i32> {
+        let code = match *self {
+            Runnable::Cmd(ref cmd) => cmd.run(shell, conf),
+            Runnable::Pipe(ref p) => p.run(shell, conf),
+            // The status is inverted, so a failure of the pipeline does not trigger `errexit`
+            Runnable::Not(ref cmd) => match shell.run_condition(cmd) {
+                Some(0) => Some(1),
+                _ => Some(0)
+            },
+            Runnable::Redirected(ref r) => r.run(shell, conf),
+            Runnable::List(ref list) => {
+                let mut code = Some(0);
+                for cmd in list.iter() {
+                    code = cmd.run(shell, RunConfig::default());
+                    if shell.is_interrupted() {
+                        break;
+                    }
+                }
+                code
+            }
+            Runnable::AndOr(ref a) => a.run(shell, conf),
+            Runnable::Group(ref g) => g.run(shell, conf),
+            Runnable::Subshell(ref s) => s.run(shell, conf),
+            Runnable::Background(ref b) => b.run(shell, conf),
+            Runnable::If(ref i) => i.run(shell, conf),
+            Runnable::Case(ref c) => c.run(shell, conf),
+            Runnable::For(ref f) => f.run(shell, conf),
+            Runnable::ArithFor(ref f) => f.run(shell, conf),
+            Runnable::Arith(ref a) => a.run(shell, conf),
+            Runnable::Cond(ref c) => c.run(shell, conf),
+            Runnable::While(ref w) => w.run(shell, conf),
+            Runnable::Function(ref f) => f.run(shell, conf),
+        };
+        shell.last_status = code;
         match *self {
-            Runnable::Cmd(ref mut cmd) => cmd.run(conf),
-            Runnable::Pipe(ref mut p) => p.run(conf),
-            Runnable::Insert(ref mut i) => i.run(conf),
-            Runnable::From => None,
-            Runnable::Append(ref mut a) => a.run(conf)
+            Runnable::Cmd(_) | Runnable::Pipe(_) | Runnable::Subshell(_) | Runnable::Arith(_) |
+            Runnable::Cond(_) => shell.check_errexit(code),
+            _ => {}
         }
+        code
     }
 }
 
@@ -99,15 +159,117 @@ impl RunConfigBuilder {
     }
 }
 
-#[derive(Debug)]
-pub struct Command<'a> {
-    pub args: Vec<Cow<'a, str>>
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectKind {
+    /// `<`
+    From,
+    /// `>`
+    Insert,
+    /// `>>`
+    Append,
+    /// `>&` and `<&`, the target is another file descriptor
+    Dup,
+}
+
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    pub fd: i32,
+    pub kind: RedirectKind,
+    /// Raw word naming the file or the descriptor
+    pub target: String,
+}
+
+impl Redirect {
+    pub fn new(fd: i32, kind: RedirectKind, target: String) -> Redirect {
+        Redirect {
+            fd,
+            kind,
+            target,
+        }
+    }
+}
+
+/// Builtins whose `name=value` arguments are expanded like assignments
+static DECLARATION_BUILTINS: &'static [&'static str] = &["declare", "local", "export", "readonly"];
+
+/// `name=value` word in front of a command, or `name[subscript]=value`
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub name: String,
+    pub subscript: Option<String>,
+    pub value: String,
+    /// The value is the list of an array assignment like `arr=(a b)`, with the parentheses
+    pub array: bool,
+}
+
+impl Assignment {
+    /// Splits a word into an assignment, returns None if the word is not one
+    pub fn parse(word: &str) -> Option<Assignment> {
+        let idx = word.find('=')?;
+        let target = &word[..idx];
+        let (name, subscript) = match target.find('[') {
+            Some(open) if target.ends_with(']') => (&target[..open], Some(target[open + 1..target.len() - 1].to_string())),
+            _ => (target, None)
+        };
+        if !is_valid_name(name) {
+            return None;
+        }
+        let value = &word[idx + 1..];
+        Some(Assignment {
+            name: name.to_string(),
+            subscript,
+            value: value.to_string(),
+            array: value.len() >= 2 && value.starts_with('(') && value.ends_with(')'),
+        })
+    }
+
+    /// Returns the assignment with its subscript and value expanded,
+    /// the list of an array assignment is expanded when it is assigned
+    pub fn expand(&self, shell: &mut Shell) -> Assignment {
+        Assignment {
+            name: self.name.clone(),
+            subscript: self.subscript.as_ref().map(|subscript| expand_word(shell, subscript)),
+            value: if self.array { self.value.clone() } else { expand_word(shell, &self.value) },
+            array: self.array,
+        }
+    }
+
+    /// Assigns the expanded assignment to the shell variable
+    pub fn assign(&self, shell: &mut Shell) -> Result<(), String> {
+        match self.subscript {
+            Some(ref subscript) if self.array => Err(format!("{}[{}]: cannot assign list to array member", self.name, subscript)),
+            Some(ref subscript) => shell.assign_element(&self.name, subscript, self.value.clone()),
+            None if self.array => {
+                let elements = expand_array(shell, &self.value[1..self.value.len() - 1])?;
+                shell.assign_array(&self.name, elements)
+            }
+            None => shell.assign(&self.name, self.value.clone())
+        }
+    }
+}
+
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.subscript {
+            Some(ref subscript) => write!(f, "{}[{}]={}", self.name, subscript, self.value),
+            None => write!(f, "{}={}", self.name, self.value)
+        }
+    }
 }
 
-impl<'a> Command<'a> {
-    pub fn new(args: Vec<Cow<'a, str>>) -> Command<'a> {
+#[derive(Debug, Clone)]
+pub struct Command {
+    pub assignments: Vec<Assignment>,
+    pub args: Vec<String>,
+    pub redirects: Vec<Redirect>,
+}
+
+impl Command {
+    pub fn new(assignments: Vec<Assignment>, args: Vec<String>, redirects: Vec<Redirect>) -> Command {
         Command {
-            args
+            assignments,
+            args,
+            redirects,
         }
     }
 
@@ -119,171 +281,755 @@ impl<'a> Command<'a> {
         self.args[0].as_ref()
     }
 
+    /// Returns the command given as the first argument, like the one run by `command` or `exec`,
+    /// without the redirections which are already applied
+    pub fn shifted(&self) -> Command {
+        Command::new(self.assignments.clone(), self.args[1..].to_vec(), Vec::new())
+    }
+
+    #[cfg(not(unix))]
     pub fn is_builtin(&self) -> bool {
         get_builtin(self.command()).is_some()
     }
-}
 
-impl<'a> RunnableCmd for Command<'a> {
-    fn run(&mut self, conf: RunConfig) -> Option<i32> {
-        if self.empty() {
-            return Some(0);
+    /// Returns the command with its arguments and assigned values expanded, ready to be run
+    #[cfg(not(unix))]
+    pub fn expand(&self, shell: &mut Shell) -> Command {
+        let args = self.expand_args(shell);
+        let assignments = self.assignments.iter().map(|assignment| assignment.expand(shell)).collect();
+        Command::new(assignments, args, self.redirects.clone())
+    }
+
+    /// Expands the arguments, assignments given to declaration builtins like `export`
+    /// are expanded as a single word like the ones in front of a command
+    fn expand_args(&self, shell: &mut Shell) -> Vec<String> {
+        let declaration = self.args.first().map_or(false, |name| DECLARATION_BUILTINS.contains(&name.as_str()));
+        if !declaration {
+            return expand_words(shell, &self.args);
+        }
+        let mut args = Vec::with_capacity(self.args.len());
+        for arg in self.args.iter() {
+            match Assignment::parse(arg) {
+                Some(assignment) => args.push(assignment.expand(shell).to_string()),
+                None => args.extend(expand_words(shell, &[arg.clone()]))
+            }
+        }
+        args
+    }
+
+    /// Runs the expanded command, which is not empty
+    fn run_expanded_with(&self, shell: &mut Shell, conf: RunConfig) -> Option<i32> {
+        if let Some(body) = shell.functions.get(self.command()).cloned() {
+            return shell.call_function(&body, &self.args[1..]);
         }
         match get_builtin(self.command()) {
-            Some(builtin) => builtin(self),
-            None => run_process(self, conf)
+            Some(_) if self.command() == "command" => builtin::command_with(shell, self, conf),
+            Some(builtin) => builtin(shell, self),
+            None if shell.options.autocd && self.is_directory(shell) => {
+                let cd = Command::new(Vec::new(), vec!["cd".to_string(), "--".to_string(), self.args[0].clone()], Vec::new());
+                builtin::cd(shell, &cd)
+            }
+            None => run_process(self, &shell.vars, conf)
         }
     }
+
+    /// Returns true if the command runs the `exec` builtin without a command, also through
+    /// `builtin` and `command`, unless the first word is a function
+    fn is_bare_exec(&self, shell: &Shell) -> bool {
+        if shell.functions.contains_key(self.command()) {
+            return false;
+        }
+        let mut args = self.args.iter().map(|arg| arg.as_str()).peekable();
+        while let Some(arg) = args.next() {
+            match arg {
+                "exec" => return args.next().is_none(),
+                "builtin" => {}
+                "command" => {
+                    // The options of `command`, `-v` and `-V` only describe the command
+                    while let Some(&opt) = args.peek() {
+                        match opt {
+                            "--" => {
+                                args.next();
+                                break;
+                            }
+                            "-p" => {
+                                args.next();
+                            }
+                            _ if opt.starts_with('-') && opt.len() > 1 => return false,
+                            _ => break
+                        }
+                    }
+                }
+                _ => return false
+            }
+        }
+        false
+    }
+
+    /// Returns true if the command is only the name of a directory and no program has that name
+    fn is_directory(&self, shell: &Shell) -> bool {
+        self.args.len() == 1 && Path::new(self.command()).is_dir() &&
+            find_executable(self.command(), &shell.vars).is_none()
+    }
 }
 
-#[derive(Debug)]
-pub struct Pipe<'a> {
-    pub from: Command<'a>,
-    pub to: Command<'a>,
+impl RunnableCmd for Command {
+    fn run(&self, shell: &mut Shell, conf: RunConfig) -> Option<i32> {
+        // Process substitutions in the arguments live until the command finishes
+        let mark = shell.process_subs.len();
+        let code = self.run_expanded(shell, conf);
+        procsub::finish(shell, mark);
+        code
+    }
 }
 
-impl<'a> Pipe<'a> {
-    pub fn new(from: Command<'a>, to: Command<'a>) -> Pipe<'a> {
-        Pipe {
-            from,
-            to,
+impl Command {
+    fn run_expanded(&self, shell: &mut Shell, mut conf: RunConfig) -> Option<i32> {
+        let mut cmd = Command::new(Vec::new(), self.expand_args(shell), self.redirects.clone());
+        let mut code = Some(0);
+        for assignment in self.assignments.iter() {
+            let assignment = assignment.expand(shell);
+            if cmd.empty() {
+                // Without a command the assignments set shell variables, one after another
+                if let Err(e) = assignment.assign(shell) {
+                    eprintln!("{}", e);
+                    code = Some(1);
+                }
+            } else {
+                cmd.assignments.push(assignment);
+            }
+        }
+        let guard = match redirect::apply(shell, &cmd.redirects, &mut conf) {
+            Ok(guard) => guard,
+            Err(e) => {
+                eprintln!("{}", e);
+                return Some(1);
+            }
+        };
+        if cmd.empty() {
+            return code;
+        }
+        // `exec` without a command keeps its redirections for the rest of the session
+        if cmd.is_bare_exec(shell) {
+            guard.keep();
+        }
+        shell.last_arg = cmd.args[cmd.args.len() - 1].clone();
+        if shell.options.xtrace {
+            let mut words: Vec<String> = cmd.assignments.iter().map(|assignment| assignment.to_string()).collect();
+            words.extend(cmd.args.iter().cloned());
+            eprintln!("+ {}", words.join(" "));
         }
+        if cmd.assignments.is_empty() {
+            return cmd.run_expanded_with(shell, conf);
+        }
+        // Builtins and functions see the assignments as exported variables until they finish,
+        // processes get them in their environment
+        shell.vars.push_scope();
+        for assignment in cmd.assignments.iter() {
+            shell.vars.make_local(&assignment.name);
+            if let Err(e) = assignment.assign(shell) {
+                eprintln!("{}", e);
+                shell.vars.pop_scope();
+                return Some(1);
+            }
+            shell.vars.declare(&assignment.name).exported = true;
+        }
+        let code = cmd.run_expanded_with(shell, conf);
+        shell.vars.pop_scope();
+        code
     }
 }
 
-impl<'a> RunnableCmd for Pipe<'a> {
-    fn run(&mut self, _conf: RunConfig) -> Option<i32> {
-        if self.from.empty() {
-            eprintln!("No command before pipe, ignoring it!");
-            return self.to.run(RunConfig::default());
+/// Commands joined by `|`, the output of each one is the input of the next
+pub struct Pipe {
+    pub commands: Vec<Runnable>,
+}
+
+impl Pipe {
+    pub fn new(commands: Vec<Runnable>) -> Pipe {
+        Pipe {
+            commands
         }
-        if self.from.is_builtin() || self.to.is_builtin() {
-            eprintln!("Builtin redirection not supported for now!");
-            return Some(12);
+    }
+
+    /// Every command runs in its own forked shell, so builtins and compound commands can be piped
+    #[cfg(unix)]
+    fn run_forked(&self, shell: &mut Shell) -> Option<i32> {
+        let mut pids = Vec::new();
+        let mut input = None;
+        let last = self.commands.len() - 1;
+        for (i, cmd) in self.commands.iter().enumerate() {
+            let output = if i < last {
+                match job::pipe() {
+                    Ok(output) => Some(output),
+                    Err(e) => {
+                        eprintln!("Cannot create pipe: {}", e);
+                        break;
+                    }
+                }
+            } else {
+                None
+            };
+            let res = job::fork(|| {
+                if let Some(fd) = input {
+                    job::move_fd(fd, 0);
+                }
+                if let Some((read, write)) = output {
+                    job::close(read);
+                    job::move_fd(write, 1);
+                }
+                shell.run_in_child(cmd)
+            });
+            if let Some(fd) = input.take() {
+                job::close(fd);
+            }
+            if let Some((read, write)) = output {
+                job::close(write);
+                input = Some(read);
+            }
+            match res {
+                Ok(pid) => pids.push(pid),
+                Err(e) => eprintln!("Cannot fork: {}", e)
+            }
         }
-        let to_conf = RunConfig::build().output(Stdio::piped()).build();
-        let child = spawn_process(&self.from, to_conf);
-        if child.is_none() {
-            return Some(10);
+        if let Some(fd) = input {
+            job::close(fd);
         }
-        let child = child.unwrap();
-        let output = match child.wait_with_output() {
-            Ok(output) => output,
-            Err(e) => {
-                eprintln!("Error occured in first program: {}", e);
+        let mut code = None;
+        for pid in pids {
+            code = job::wait(pid);
+        }
+        code
+    }
+
+    /// Without fork only external programs can be piped, they are spawned connected to each other
+    #[cfg(not(unix))]
+    fn run_spawned(&self, shell: &mut Shell) -> Option<i32> {
+        let mut cmds = Vec::new();
+        for cmd in self.commands.iter() {
+            let cmd = match *cmd {
+                Runnable::Cmd(ref cmd) => cmd.expand(shell),
+                _ => {
+                    eprintln!("Only simple commands can be piped on this platform!");
+                    return Some(12);
+                }
+            };
+            if cmd.empty() || cmd.is_builtin() || shell.functions.contains_key(cmd.command()) {
+                eprintln!("Builtin redirection not supported for now!");
                 return Some(12);
             }
-        };
-        let to_conf = RunConfig::build().input(Stdio::piped()).build();
-        let child = spawn_process(&self.to, to_conf);
-        if child.is_none() {
-            return Some(11);
-        }
-        let mut child = child.unwrap();
-        {
-            let stdin = child.stdin.as_mut().unwrap();
-            let res = stdin.write_all(&output.stdout);
-            if let Err(e) = res {
-                eprintln!("Error occured: {}", e);
-                return Some(13);
-            }
-        }
-        match child.wait() {
-            Ok(_res) => {
+            cmds.push(cmd);
+        }
+        let mut children = Vec::new();
+        let mut input = None;
+        for (i, cmd) in cmds.iter().enumerate() {
+            let mut conf = RunConfig::build();
+            if let Some(input) = input.take() {
+                conf = conf.input(input);
+            }
+            if i + 1 < cmds.len() {
+                conf = conf.output(Stdio::piped());
+            }
+            let mut child = match spawn_process(cmd, &shell.vars, conf.build()) {
+                Ok(child) => child,
+                Err(_) => return Some(10)
+            };
+            input = child.stdout.take().map(Stdio::from);
+            children.push(child);
+        }
+        let mut code = None;
+        for mut child in children {
+            code = match child.wait() {
+                Ok(res) => res.code(),
+                Err(e) => {
+                    eprintln!("Error occured: {}", e);
+                    Some(14)
+                }
+            };
+        }
+        code
+    }
+}
+
+impl RunnableCmd for Pipe {
+    #[cfg(unix)]
+    fn run(&self, shell: &mut Shell, _conf: RunConfig) -> Option<i32> {
+        self.run_forked(shell)
+    }
+
+    #[cfg(not(unix))]
+    fn run(&self, shell: &mut Shell, _conf: RunConfig) -> Option<i32> {
+        self.run_spawned(shell)
+    }
+}
+
+/// `( list )`, runs in a separate shell so changes to variables and the directory are not kept
+pub struct Subshell {
+    pub cmd: Box<Runnable>,
+}
+
+impl Subshell {
+    pub fn new(cmd: Runnable) -> Self {
+        Self {
+            cmd: Box::new(cmd),
+        }
+    }
+}
+
+impl RunnableCmd for Subshell {
+    #[cfg(unix)]
+    fn run(&self, shell: &mut Shell, _conf: RunConfig) -> Option<i32> {
+        match job::fork(|| shell.run_in_child(&self.cmd)) {
+            Ok(pid) => job::wait(pid),
+            Err(e) => {
+                eprintln!("Cannot fork: {}", e);
+                Some(1)
+            }
+        }
+    }
+
+    /// Without fork the shell state is copied before running and restored afterwards
+    #[cfg(not(unix))]
+    fn run(&self, shell: &mut Shell, _conf: RunConfig) -> Option<i32> {
+        use std::env::{current_dir, set_current_dir};
+
+        let saved = shell.clone();
+        let dir = current_dir();
+        let code = shell.run_in_child(&self.cmd);
+        *shell = saved;
+        if let Ok(dir) = dir {
+            if let Err(e) = set_current_dir(dir) {
+                eprintln!("Cannot restore directory: {}", e);
+            }
+        }
+        code
+    }
+}
+
+pub struct Background {
+    pub cmd: Box<Runnable>,
+}
+
+impl Background {
+    pub fn new(cmd: Runnable) -> Self {
+        Self {
+            cmd: Box::new(cmd),
+        }
+    }
+}
+
+impl RunnableCmd for Background {
+    #[cfg(unix)]
+    fn run(&self, shell: &mut Shell, _conf: RunConfig) -> Option<i32> {
+        match job::fork(|| shell.run_in_child(&self.cmd)) {
+            Ok(pid) => {
+                shell.last_background = Some(pid);
+                shell.background.push(pid);
                 Some(0)
             }
             Err(e) => {
-                eprintln!("Error occured: {}", e);
-                Some(14)
+                eprintln!("Cannot fork: {}", e);
+                Some(1)
             }
         }
     }
+
+    #[cfg(not(unix))]
+    fn run(&self, shell: &mut Shell, conf: RunConfig) -> Option<i32> {
+        eprintln!("Background commands are not supported on this platform, running in the foreground");
+        self.cmd.run(shell, conf)
+    }
 }
 
-#[derive(Debug)]
-pub struct Insert<'a> {
-    pub cmd: Command<'a>,
-    pub file_name: Cow<'a, str>,
-    append: bool,
+pub struct Redirected {
+    pub cmd: Box<Runnable>,
+    pub redirects: Vec<Redirect>,
 }
 
-impl<'a> Insert<'a> {
-    pub fn new(cmd: Command<'a>, file_name: Cow<'a, str>) -> Self {
+impl Redirected {
+    pub fn new(cmd: Runnable, redirects: Vec<Redirect>) -> Self {
         Self {
-            cmd,
-            file_name,
-            append: false,
+            cmd: Box::new(cmd),
+            redirects,
         }
     }
 }
 
-// TODO: Abstract Insert, Append, From logic instead of sharing most of the code
-impl<'a> RunnableCmd for Insert<'a> {
-    fn run(&mut self, _conf: RunConfig) -> Option<i32> {
-        if self.cmd.empty() {
-            eprintln!("No command before pipe, ignoring it!");
-            return None;
+impl Redirected {
+    fn run_redirected(&self, shell: &mut Shell, mut conf: RunConfig) -> Option<i32> {
+        let _guard = match redirect::apply(shell, &self.redirects, &mut conf) {
+            Ok(guard) => guard,
+            Err(e) => {
+                eprintln!("{}", e);
+                return Some(1);
+            }
+        };
+        self.cmd.run(shell, conf)
+    }
+}
+
+impl RunnableCmd for Redirected {
+    fn run(&self, shell: &mut Shell, conf: RunConfig) -> Option<i32> {
+        let mark = shell.process_subs.len();
+        let code = self.run_redirected(shell, conf);
+        procsub::finish(shell, mark);
+        code
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+/// Commands joined by `&&` and `||`, each one runs depending on the previous exit code
+pub struct AndOr {
+    pub first: Box<Runnable>,
+    pub rest: Vec<(Connector, Runnable)>,
+}
+
+impl AndOr {
+    pub fn new(first: Runnable, rest: Vec<(Connector, Runnable)>) -> Self {
+        Self {
+            first: Box::new(first),
+            rest,
         }
-        if self.cmd.is_builtin() {
-            eprintln!("Builtin redirection not supported for now!");
-            return Some(12);
+    }
+}
+
+impl RunnableCmd for AndOr {
+    fn run(&self, shell: &mut Shell, _conf: RunConfig) -> Option<i32> {
+        // Only the last command of the list can trigger `errexit`
+        let mut code = shell.run_condition(&self.first);
+        for (i, &(connector, ref cmd)) in self.rest.iter().enumerate() {
+            if shell.is_interrupted() {
+                break;
+            }
+            let success = code == Some(0);
+            if (connector == Connector::And) == success {
+                code = if i + 1 == self.rest.len() {
+                    cmd.run(shell, RunConfig::default())
+                } else {
+                    shell.run_condition(cmd)
+                };
+            }
         }
-        let cmd_conf = RunConfig::build().output(Stdio::piped()).build();
-        let child = spawn_process(&self.cmd, cmd_conf);
-        if child.is_none() {
-            return Some(10);
+        code
+    }
+}
+
+/// `if cond; then body; elif cond; then body; else otherwise; fi`
+pub struct If {
+    pub branches: Vec<(Runnable, Runnable)>,
+    pub otherwise: Option<Box<Runnable>>,
+}
+
+impl If {
+    pub fn new(branches: Vec<(Runnable, Runnable)>, otherwise: Option<Runnable>) -> Self {
+        Self {
+            branches,
+            otherwise: otherwise.map(Box::new),
         }
-        let child = child.unwrap();
-        let output = match child.wait_with_output() {
-            Ok(output) => output,
-            Err(e) => {
-                eprintln!("Error occured in command program: {}", e);
-                return Some(12);
+    }
+}
+
+impl RunnableCmd for If {
+    fn run(&self, shell: &mut Shell, _conf: RunConfig) -> Option<i32> {
+        for &(ref cond, ref body) in self.branches.iter() {
+            let code = shell.run_condition(cond);
+            if shell.is_interrupted() {
+                return code;
+            }
+            if code == Some(0) {
+                return body.run(shell, RunConfig::default());
             }
+        }
+        match self.otherwise {
+            Some(ref otherwise) => otherwise.run(shell, RunConfig::default()),
+            None => Some(0)
+        }
+    }
+}
+
+/// `case word in pattern | pattern) body ;; esac`
+pub struct Case {
+    pub word: String,
+    pub items: Vec<CaseItem>,
+}
+
+pub struct CaseItem {
+    pub patterns: Vec<String>,
+    pub body: Runnable,
+}
+
+impl Case {
+    pub fn new(word: String, items: Vec<CaseItem>) -> Self {
+        Self {
+            word,
+            items,
+        }
+    }
+}
+
+impl RunnableCmd for Case {
+    fn run(&self, shell: &mut Shell, _conf: RunConfig) -> Option<i32> {
+        let word = expand_word(shell, &self.word);
+        for item in self.items.iter() {
+            let matched = item.patterns.iter().any(|pattern| glob::matches(&expand_pattern(shell, pattern), &word));
+            if matched {
+                return item.body.run(shell, RunConfig::default());
+            }
+        }
+        Some(0)
+    }
+}
+
+/// `for var in words; do body; done`
+pub struct For {
+    pub var: String,
+    /// None if `in` is not given, then the positional parameters are used
+    pub words: Option<Vec<String>>,
+    pub body: Box<Runnable>,
+}
+
+impl For {
+    pub fn new(var: String, words: Option<Vec<String>>, body: Runnable) -> Self {
+        Self {
+            var,
+            words,
+            body: Box::new(body),
+        }
+    }
+}
+
+impl RunnableCmd for For {
+    fn run(&self, shell: &mut Shell, _conf: RunConfig) -> Option<i32> {
+        let words = match self.words {
+            Some(ref words) => expand_words(shell, words),
+            None => shell.positional.clone()
         };
-        let mut options = OpenOptions::new();
-        if self.append {
-            options.append(true).create(true);
-        } else {
-            options.write(true).create(true).truncate(true);
-        }
-        let mut file = match options.open(self.file_name.as_ref()) {
-            Ok(f) => f,
+        let mut code = Some(0);
+        shell.loop_depth += 1;
+        for word in words {
+            if let Err(e) = shell.assign(&self.var, word) {
+                eprintln!("{}", e);
+                code = Some(1);
+                break;
+            }
+            code = self.body.run(shell, RunConfig::default());
+            if shell.end_iteration() {
+                break;
+            }
+        }
+        shell.loop_depth -= 1;
+        code
+    }
+}
+
+/// `for ((init; cond; step)); do body; done`
+pub struct ArithFor {
+    pub init: String,
+    pub cond: String,
+    pub step: String,
+    pub body: Box<Runnable>,
+}
+
+impl ArithFor {
+    pub fn new(init: String, cond: String, step: String, body: Runnable) -> Self {
+        Self {
+            init,
+            cond,
+            step,
+            body: Box::new(body),
+        }
+    }
+
+    fn run_loop(&self, shell: &mut Shell) -> Result<Option<i32>, String> {
+        let mut code = Some(0);
+        eval_arith(shell, &self.init)?;
+        // An empty condition is always true
+        while self.cond.trim().is_empty() || eval_arith(shell, &self.cond)? != 0 {
+            code = self.body.run(shell, RunConfig::default());
+            if shell.end_iteration() {
+                break;
+            }
+            eval_arith(shell, &self.step)?;
+        }
+        Ok(code)
+    }
+}
+
+impl RunnableCmd for ArithFor {
+    fn run(&self, shell: &mut Shell, _conf: RunConfig) -> Option<i32> {
+        shell.loop_depth += 1;
+        let res = self.run_loop(shell);
+        shell.loop_depth -= 1;
+        match res {
+            Ok(code) => code,
             Err(e) => {
-                eprintln!("Cannot create/open the file: {}", e);
-                return Some(13);
+                eprintln!("for: {}", e);
+                Some(1)
             }
-        };
-        match file.write_all(&output.stdout) {
-            Ok(()) => Some(0),
+        }
+    }
+}
+
+/// `((expr))`, succeeds if the expression is not zero
+pub struct Arith {
+    pub expr: String,
+}
+
+impl Arith {
+    pub fn new(expr: String) -> Self {
+        Self { expr }
+    }
+}
+
+impl RunnableCmd for Arith {
+    fn run(&self, shell: &mut Shell, _conf: RunConfig) -> Option<i32> {
+        match eval_arith(shell, &self.expr) {
+            Ok(0) => Some(1),
+            Ok(_) => Some(0),
+            Err(e) => {
+                eprintln!("((: {}", e);
+                Some(1)
+            }
+        }
+    }
+}
+
+/// `[[ expr ]]`, the words are expanded while the expression is evaluated, without word splitting
+pub struct Cond {
+    pub words: Vec<String>,
+}
+
+impl Cond {
+    pub fn new(words: Vec<String>) -> Self {
+        Self { words }
+    }
+}
+
+impl RunnableCmd for Cond {
+    fn run(&self, shell: &mut Shell, _conf: RunConfig) -> Option<i32> {
+        match test::eval_cond(shell, &self.words) {
+            Ok(true) => Some(0),
+            Ok(false) => Some(1),
             Err(e) => {
-                eprintln!("Cannot write the text: {}", e);
-                Some(14)
+                eprintln!("[[: {}", e);
+                Some(2)
             }
         }
     }
 }
 
+/// Expands the parameters in an arithmetic expression and evaluates it
+fn eval_arith(shell: &mut Shell, expr: &str) -> Result<i64, String> {
+    let expr = expand_arith(shell, expr);
+    arith::eval(shell, &expr)
+}
 
-#[derive(Debug)]
-pub struct Append<'a> {
-    insert: Insert<'a>
+/// `while cond; do body; done`, or `until` if `until` is true
+pub struct While {
+    pub cond: Box<Runnable>,
+    pub body: Box<Runnable>,
+    pub until: bool,
 }
 
-impl<'a> Append<'a> {
-    pub fn new(cmd: Command<'a>, file_name: Cow<'a, str>) -> Self {
+impl While {
+    pub fn new(cond: Runnable, body: Runnable, until: bool) -> Self {
         Self {
-            insert: Insert {
-                cmd,
-                file_name,
-                append: true,
+            cond: Box::new(cond),
+            body: Box::new(body),
+            until,
+        }
+    }
+}
+
+impl RunnableCmd for While {
+    fn run(&self, shell: &mut Shell, _conf: RunConfig) -> Option<i32> {
+        let mut code = Some(0);
+        shell.loop_depth += 1;
+        loop {
+            let cond = shell.run_condition(&self.cond);
+            if shell.is_interrupted() {
+                if shell.end_iteration() {
+                    break;
+                }
+                continue;
+            }
+            if (cond == Some(0)) == self.until {
+                break;
+            }
+            code = self.body.run(shell, RunConfig::default());
+            if shell.end_iteration() {
+                break;
             }
         }
+        shell.loop_depth -= 1;
+        code
     }
 }
 
-impl<'a> RunnableCmd for Append<'a> {
-    fn run(&mut self, conf: RunConfig) -> Option<i32> {
-        self.insert.run(conf)
+/// `name() { body; }`, defines the function when run
+pub struct FunctionDef {
+    pub name: String,
+    pub body: Rc<Runnable>,
+}
+
+impl FunctionDef {
+    pub fn new(name: String, body: Runnable) -> Self {
+        Self {
+            name,
+            body: Rc::new(body),
+        }
+    }
+}
+
+impl RunnableCmd for FunctionDef {
+    fn run(&self, shell: &mut Shell, _conf: RunConfig) -> Option<i32> {
+        shell.functions.insert(self.name.clone(), self.body.clone());
+        Some(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser;
+
+    fn run(shell: &mut Shell, line: &str) -> Option<i32> {
+        parser::parse(line).unwrap().run(shell, RunConfig::default())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn temporary_assignments_are_exported() {
+        let mut shell = Shell::new();
+        // The test harness captures the output of processes, so only the status of printenv is checked
+        assert_eq!(run(&mut shell, "f() { printenv FOO; }"), Some(0));
+        assert_eq!(run(&mut shell, "FOO=1 f"), Some(0));
+        assert_eq!(run(&mut shell, "f"), Some(1));
+        // The variable and its attributes are restored after the function
+        assert_eq!(shell.vars.get("FOO"), None);
+        run(&mut shell, "BAR=old");
+        run(&mut shell, "BAR=new true");
+        let bar = shell.vars.get_var("BAR").unwrap();
+        assert_eq!((bar.value.as_ref().and_then(|value| value.scalar()), bar.exported), (Some("old"), false));
+    }
+
+    #[test]
+    fn assignment_subscripts() {
+        let assignment = Assignment::parse("arr[$i + 1]=x=y").unwrap();
+        assert_eq!((assignment.name.as_str(), assignment.value.as_str()), ("arr", "x=y"));
+        assert_eq!((assignment.subscript, assignment.array), (Some("$i + 1".to_string()), false));
+        let assignment = Assignment::parse("arr=(a b)").unwrap();
+        assert_eq!((assignment.subscript.is_none(), assignment.array), (true, true));
+        let assignment = Assignment::parse("m[key]=").unwrap();
+        assert_eq!((assignment.subscript.unwrap(), assignment.value), ("key".to_string(), String::new()));
+        // `(` alone is not a list
+        assert!(!Assignment::parse("a=(").unwrap().array);
+        for word in &["=a", "1a=b", "a[0=b", "[0]=b", "a-b[0]=c", "echo"] {
+            assert!(Assignment::parse(word).is_none(), "{}", word);
+        }
     }
 }
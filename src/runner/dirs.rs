@@ -0,0 +1,316 @@
+/*
+ * Project: rusth
+ * File: runner/dirs.rs
+ * Copyright 2018 Caner Korkmaz (Kausta) <info@canerkorkmaz.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::env::{self, home_dir};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use super::shell::Shell;
+use super::vars::Variables;
+
+/// Returns the logical current directory, which is `$PWD` as long as it names the current directory,
+/// so symbolic links followed by `cd` are kept
+pub fn current(vars: &Variables) -> PathBuf {
+    let physical = match env::current_dir() {
+        Ok(dir) => dir,
+        Err(_) => return PathBuf::from(vars.get("PWD").unwrap_or(".")),
+    };
+    match vars.get("PWD") {
+        Some(pwd) if Path::new(pwd).is_absolute() && fs::canonicalize(pwd).ok() == Some(physical.clone()) => {
+            PathBuf::from(pwd)
+        }
+        _ => physical
+    }
+}
+
+/// Sets `$PWD` for the processes started by the shell, unless the inherited one is still right
+pub fn init(vars: &mut Variables) {
+    let pwd = current(vars);
+    let _ = set_exported(vars, "PWD", &pwd);
+}
+
+/// Removes the `.` and `..` components without resolving symbolic links
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            component => result.push(component.as_os_str()),
+        }
+    }
+    result
+}
+
+/// Changes the current directory and updates `$PWD` and `$OLDPWD`, `physical` resolves
+/// the symbolic links in the new `$PWD` instead of keeping the path as it was given
+pub fn change(vars: &mut Variables, dir: &Path, physical: bool) -> Result<(), String> {
+    let old = current(vars);
+    let new = if physical {
+        env::set_current_dir(dir)
+            .and_then(|_| env::current_dir())
+            .map_err(|e| format!("{}: {}", dir.display(), e))?
+    } else {
+        let logical = normalize(&old.join(dir));
+        env::set_current_dir(&logical).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        logical
+    };
+    set_exported(vars, "OLDPWD", &old)?;
+    set_exported(vars, "PWD", &new)
+}
+
+/// Looks a relative directory up in the directories of `CDPATH`, returns None if it is not found
+/// in them or if an empty entry, which stands for the current directory, comes first
+pub fn search_cdpath(vars: &Variables, dir: &str) -> Option<PathBuf> {
+    let first = Path::new(dir).components().next();
+    match first {
+        Some(Component::Normal(_)) => {}
+        _ => return None
+    }
+    let cdpath = vars.get("CDPATH")?;
+    for entry in env::split_paths(cdpath) {
+        if entry.as_os_str().is_empty() {
+            if Path::new(dir).is_dir() {
+                return None;
+            }
+            continue;
+        }
+        let candidate = entry.join(dir);
+        if candidate.is_dir() {
+            return Some(candidate);
+        }
+    }
+    None
+}
+
+fn set_exported(vars: &mut Variables, name: &str, path: &Path) -> Result<(), String> {
+    vars.set(name, path.display().to_string())?;
+    vars.declare(name).exported = true;
+    Ok(())
+}
+
+/// Returns the directory stack as listed by `dirs`, starting with the current directory
+pub fn stack(shell: &Shell) -> Vec<PathBuf> {
+    let mut stack = vec![current(&shell.vars)];
+    stack.extend(shell.dir_stack.iter().cloned());
+    stack
+}
+
+/// Returns the position in a stack of `len` directories for `+N`, counted from the left
+/// starting with the current directory, `-N`, counted from the right, or `N` like `+N`
+pub fn stack_index(len: usize, arg: &str) -> Option<usize> {
+    let (from_right, digits) = if arg.starts_with('-') {
+        (true, &arg[1..])
+    } else if arg.starts_with('+') {
+        (false, &arg[1..])
+    } else {
+        (false, arg)
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let n: usize = digits.parse().ok()?;
+    if n >= len {
+        return None;
+    }
+    Some(if from_right { len - 1 - n } else { n })
+}
+
+/// Returns the directory for the prefix of a tilde expansion, the text between `~` and the first `/`,
+/// None if it is a prefix that is not expanded like an unknown user name
+pub fn tilde(shell: &Shell, prefix: &str) -> Option<String> {
+    match prefix {
+        "" => shell.vars.get("HOME").map(|home| home.to_string())
+            .or_else(|| home_dir().map(|home| home.display().to_string())),
+        "+" => shell.vars.get("PWD").map(|pwd| pwd.to_string()),
+        "-" => shell.vars.get("OLDPWD").map(|old| old.to_string()),
+        _ => {
+            let stack = stack(shell);
+            stack_index(stack.len(), prefix).map(|i| stack[i].display().to_string())
+        }
+    }
+}
+
+/// Replaces the home directory at the start of the path with `~`
+pub fn abbreviate(vars: &Variables, path: &Path) -> String {
+    let path = path.display().to_string();
+    if let Some(home) = vars.get("HOME") {
+        let home = home.trim_end_matches('/');
+        if !home.is_empty() && (path == home || path.starts_with(&format!("{}/", home))) {
+            return format!("~{}", &path[home.len()..]);
+        }
+    }
+    path
+}
+
+/// Returns the existing directory with the closest spelling to a missing one, each missing
+/// component of the path is replaced with the closest name next to it if that is close enough to be a typo
+pub fn correct_spelling(dir: &Path) -> Option<PathBuf> {
+    let mut corrected = PathBuf::new();
+    for component in dir.components() {
+        let next = corrected.join(component.as_os_str());
+        match component {
+            Component::Normal(name) if !next.exists() => {
+                let parent = if corrected.as_os_str().is_empty() { Path::new(".") } else { corrected.as_path() };
+                let name = closest_name(parent, &name.to_string_lossy())?;
+                corrected.push(name);
+            }
+            _ => corrected = next
+        }
+    }
+    if corrected.is_dir() {
+        Some(corrected)
+    } else {
+        None
+    }
+}
+
+/// Returns the name of the directory in `parent` closest to `name`
+fn closest_name(parent: &Path, name: &str) -> Option<String> {
+    let max_distance = name.chars().count() / 3 + 1;
+    fs::read_dir(parent).ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let candidate = entry.file_name().to_string_lossy().into_owned();
+            let distance = edit_distance(name, &candidate);
+            if distance <= max_distance {
+                Some((distance, candidate))
+            } else {
+                None
+            }
+        })
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between the two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            let value = (previous[j] + cost).min(previous[j + 1] + 1).min(current[j] + 1);
+            current.push(value);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell_with_stack() -> Shell {
+        let mut shell = Shell::new();
+        shell.vars.set("HOME", "/home/user".to_string()).unwrap();
+        shell.vars.set("PWD", "/pwd".to_string()).unwrap();
+        shell.vars.set("OLDPWD", "/old".to_string()).unwrap();
+        shell.dir_stack = vec![PathBuf::from("/a"), PathBuf::from("/b")];
+        shell
+    }
+
+    #[test]
+    fn stack_positions() {
+        assert_eq!(stack_index(3, "0"), Some(0));
+        assert_eq!(stack_index(3, "+2"), Some(2));
+        assert_eq!(stack_index(3, "-0"), Some(2));
+        assert_eq!(stack_index(3, "-2"), Some(0));
+        assert_eq!(stack_index(3, "+3"), None);
+        assert_eq!(stack_index(3, "-3"), None);
+        assert_eq!(stack_index(3, "99999999999999999999999"), None);
+        assert_eq!(stack_index(3, "+"), None);
+        assert_eq!(stack_index(3, "-"), None);
+        assert_eq!(stack_index(3, "1a"), None);
+        assert_eq!(stack_index(3, "+-1"), None);
+        assert_eq!(stack_index(0, "0"), None);
+    }
+
+    #[test]
+    fn tilde_prefixes() {
+        let shell = shell_with_stack();
+        assert_eq!(tilde(&shell, ""), Some("/home/user".to_string()));
+        assert_eq!(tilde(&shell, "+"), Some("/pwd".to_string()));
+        assert_eq!(tilde(&shell, "-"), Some("/old".to_string()));
+        assert_eq!(tilde(&shell, "1"), Some("/a".to_string()));
+        assert_eq!(tilde(&shell, "+2"), Some("/b".to_string()));
+        assert_eq!(tilde(&shell, "-0"), Some("/b".to_string()));
+        assert_eq!(tilde(&shell, "-1"), Some("/a".to_string()));
+        assert_eq!(tilde(&shell, "0"), Some(current(&shell.vars).display().to_string()));
+        assert_eq!(tilde(&shell, "3"), None);
+        assert_eq!(tilde(&shell, "-3"), None);
+        assert_eq!(tilde(&shell, "nosuchuser"), None);
+    }
+
+    #[test]
+    fn distances() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("docs", "docs"), 0);
+        assert_eq!(edit_distance("docs", "dcos"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("srcc", "src"), 1);
+        assert_eq!(edit_distance("çalış", "calis"), 3);
+    }
+
+    /// Creates a directory with the given subdirectories and a file named `file`
+    fn temp_tree(name: &str, dirs: &[&str]) -> PathBuf {
+        let root = env::temp_dir().join(format!("rusth-dirs-{}-{}", name, ::std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in dirs {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("file"), "").unwrap();
+        root
+    }
+
+    #[test]
+    fn closest_names() {
+        let root = temp_tree("closest", &["documents", "abd", "abe", "xy"]);
+        assert_eq!(closest_name(&root, "documnets"), Some("documents".to_string()));
+        // Ties go to the first name in order
+        assert_eq!(closest_name(&root, "abc"), Some("abd".to_string()));
+        // Names of 2 characters allow 1 change, files are never suggested
+        assert_eq!(closest_name(&root, "xz"), Some("xy".to_string()));
+        assert_eq!(closest_name(&root, "zz"), None);
+        assert_eq!(closest_name(&root, "fil"), None);
+        // `len / 3 + 1` changes are allowed, 2 for 4 or 5 characters and 3 for 6
+        assert_eq!(closest_name(&root, "xyzz"), Some("xy".to_string()));
+        assert_eq!(closest_name(&root, "xyzzz"), None);
+        assert_eq!(closest_name(&root, "docume"), Some("documents".to_string()));
+        assert_eq!(closest_name(&root, "docum"), None);
+        assert_eq!(closest_name(&root.join("missing"), "abd"), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn corrected_paths() {
+        let root = temp_tree("spelling", &["projects/rusth/src", "music"]);
+        assert_eq!(correct_spelling(&root.join("projcts/rsth/src")), Some(root.join("projects/rusth/src")));
+        assert_eq!(correct_spelling(&root.join("projects/rusht")), Some(root.join("projects/rusth")));
+        assert_eq!(correct_spelling(&root.join("projects/nothing/src")), None);
+        assert_eq!(correct_spelling(&root.join("fle")), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
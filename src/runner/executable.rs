@@ -17,23 +17,24 @@
 */
 
 use super::command::{Command as Cmd, RunConfig};
+use super::vars::Variables;
 
-use std::io;
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, Write};
 use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Child};
 use std::ops::Deref;
 
-pub fn run_process(cmd: &Cmd, run_config: RunConfig) -> Option<i32> {
-    let mut child = match spawn_process(cmd, run_config) {
-        Some(child) => child,
-        None => { return Some(-1); }
+pub fn run_process(cmd: &Cmd, vars: &Variables, run_config: RunConfig) -> Option<i32> {
+    let mut child = match spawn_process(cmd, vars, run_config) {
+        Ok(child) => child,
+        Err(e) => return Some(not_started_status(&e))
     };
     let res = child.wait();
     match res {
-        Ok(exit_status) => {
-            println!("{0} finished", cmd.command());
-            exit_status.code()
-        }
+        Ok(exit_status) => exit_status.code(),
         Err(e) => {
             eprintln!("Cannot wait for {0}: {1}", cmd.command(), e);
             Some(-2)
@@ -41,27 +42,104 @@ pub fn run_process(cmd: &Cmd, run_config: RunConfig) -> Option<i32> {
     }
 }
 
-pub fn spawn_process(cmd: &Cmd, run_config: RunConfig) -> Option<Child> {
+/// Status of a command that could not be started, 127 if it was not found and 126 otherwise
+pub fn not_started_status(e: &io::Error) -> i32 {
+    if e.kind() == io::ErrorKind::NotFound {
+        127
+    } else {
+        126
+    }
+}
+
+/// Returns the exported variables, replaced or extended by the assignments of the command
+pub fn environment<'a>(cmd: &'a Cmd, vars: &'a Variables) -> HashMap<&'a str, &'a str> {
+    let mut env: HashMap<&str, &str> = vars.exported().into_iter().collect();
+    for assignment in cmd.assignments.iter().filter(|assignment| assignment.subscript.is_none() && !assignment.array) {
+        env.insert(&assignment.name, &assignment.value);
+    }
+    env
+}
+
+/// Returns the first file named `name` in the directories of `PATH`
+pub fn find_in_path(name: &str, vars: &Variables) -> Option<PathBuf> {
+    search_path(name, vars, |file| file.is_file())
+}
+
+/// Returns the program that would be run for `name` from the directories of `PATH`
+pub fn find_executable(name: &str, vars: &Variables) -> Option<PathBuf> {
+    search_path(name, vars, is_executable)
+}
+
+fn search_path<F: Fn(&Path) -> bool>(name: &str, vars: &Variables, accept: F) -> Option<PathBuf> {
+    let path = vars.get("PATH")?;
+    env::split_paths(path)
+        .map(|dir| dir.join(name))
+        .find(|file| accept(file))
+}
+
+#[cfg(unix)]
+pub fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+pub fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Replaces the shell with the command, only returns if the command cannot be started
+#[cfg(unix)]
+pub fn exec_process(cmd: &Cmd, vars: &Variables) -> io::Error {
+    use std::os::unix::process::CommandExt;
+    let _ = io::stdout().flush();
+    Command::new(cmd.command())
+        .args(&cmd.args[1..])
+        .env_clear()
+        .envs(environment(cmd, vars))
+        .exec()
+}
+
+/// Processes cannot replace the shell here, so the shell exits with the status of the command
+#[cfg(not(unix))]
+pub fn exec_process(cmd: &Cmd, vars: &Variables) -> io::Error {
+    let _ = io::stdout().flush();
+    let res = spawn_process_impl(cmd.command(),
+                                 cmd.args.iter().skip(1),
+                                 environment(cmd, vars),
+                                 RunConfig::default())
+        .and_then(|mut child| child.wait());
+    match res {
+        Ok(status) => ::std::process::exit(status.code().unwrap_or(1)),
+        Err(e) => e
+    }
+}
+
+/// Starts the command with the exported variables and its own assignments as the environment
+pub fn spawn_process(cmd: &Cmd, vars: &Variables, run_config: RunConfig) -> io::Result<Child> {
     let res = spawn_process_impl(cmd.command(),
                                  cmd.args.iter().map(|item| item.deref()).skip(1),
+                                 environment(cmd, vars),
                                  run_config);
-    match res {
-        Ok(child) => {
-            Some(child)
-        }
-        Err(e) => {
-            eprintln!("{0} failed to start: {1}", cmd.command(), e);
-            None
-        }
+    if let Err(ref e) = res {
+        eprintln!("{0} failed to start: {1}", cmd.command(), e);
     }
+    res
 }
 
 
-fn spawn_process_impl<I, S>(process_name: &str, args: I, conf: RunConfig) -> io::Result<Child>
+fn spawn_process_impl<I, S, E, K, V>(process_name: &str, args: I, env: E, conf: RunConfig) -> io::Result<Child>
     where I: IntoIterator<Item=S>,
-          S: AsRef<OsStr> {
+          S: AsRef<OsStr>,
+          E: IntoIterator<Item=(K, V)>,
+          K: AsRef<OsStr>,
+          V: AsRef<OsStr> {
     let mut cmd = Command::new(process_name);
     cmd.args(args);
+    cmd.env_clear();
+    cmd.envs(env);
     if let Some(stdin) = conf.input {
         cmd.stdin(stdin);
     }
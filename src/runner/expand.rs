@@ -0,0 +1,634 @@
+/*
+ * Project: rusth
+ * File: runner/expand.rs
+ * Copyright 2018 Caner Korkmaz (Kausta) <info@canerkorkmaz.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::iter::Peekable;
+use std::mem;
+use std::str::Chars;
+
+use parser;
+use util::glob;
+use super::arith;
+use super::dirs;
+use super::procsub;
+use super::shell::Shell;
+use super::vars::is_valid_name;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quoting {
+    /// Text written unquoted in the word
+    Unquoted,
+    /// Text in quotes or escaped with a backslash
+    Quoted,
+    /// Result of an unquoted expansion
+    Expanded,
+}
+
+#[derive(Debug)]
+struct Piece {
+    text: String,
+    quoting: Quoting,
+}
+
+/// Value of `IFS` when it is not set
+pub const DEFAULT_IFS: &'static str = " \t\n";
+
+/// Expands raw words into the final arguments, a word can expand into multiple arguments.
+/// Parameter expansion is followed by field splitting and then by pathname expansion.
+pub fn expand_words(shell: &mut Shell, words: &[String]) -> Vec<String> {
+    let mut res = Vec::with_capacity(words.len());
+    for word in words {
+        let fields = Expander::new(shell, word).expand();
+        let ifs = shell.vars.get("IFS").unwrap_or(DEFAULT_IFS);
+        for field in fields {
+            for field in split_field(ifs, field) {
+                res.extend(expand_pathname(&field));
+            }
+        }
+    }
+    res
+}
+
+/// Expands a raw word into a single string, without pathname expansion,
+/// multiple fields like the elements of an array are joined with spaces
+pub fn expand_word(shell: &mut Shell, word: &str) -> String {
+    let fields = Expander::new(shell, word).expand();
+    let fields: Vec<String> = fields.iter()
+        .map(|field| field.iter().map(|piece| piece.text.as_str()).collect())
+        .collect();
+    fields.join(" ")
+}
+
+/// Expands the parameters in an arithmetic expression, a leading `~` and `<(` are operators
+/// there, so tilde expansion and process substitution are not done
+pub fn expand_arith(shell: &mut Shell, expr: &str) -> String {
+    let mut expander = Expander::new(shell, expr);
+    expander.arithmetic = true;
+    let fields: Vec<String> = expander.expand().iter()
+        .map(|field| field.iter().map(|piece| piece.text.as_str()).collect())
+        .collect();
+    fields.join(" ")
+}
+
+/// Expands a raw word into a glob pattern, quoted characters are escaped to match literally
+pub fn expand_pattern(shell: &mut Shell, word: &str) -> String {
+    let fields = Expander::new(shell, word).expand();
+    let fields: Vec<String> = fields.iter().map(|field| escape_quoted(field, glob::is_special)).collect();
+    fields.join(" ")
+}
+
+/// Expands a raw word into an extended regular expression, quoted characters match literally
+pub fn expand_regex(shell: &mut Shell, word: &str) -> String {
+    let fields = Expander::new(shell, word).expand();
+    let fields: Vec<String> = fields.iter().map(|field| escape_quoted(field, is_regex_special)).collect();
+    fields.join(" ")
+}
+
+/// Expands the inside of an array assignment like `(a [2]=b)`, elements with a subscript
+/// are returned with it
+pub fn expand_array(shell: &mut Shell, inner: &str) -> Result<Vec<(Option<String>, String)>, String> {
+    let words = parser::split_words(inner).map_err(|e| e.to_string())?;
+    let mut elements = Vec::new();
+    for word in words {
+        if word.starts_with('[') {
+            if let Some(idx) = word.find("]=") {
+                let key = expand_word(shell, &word[1..idx]);
+                let value = expand_word(shell, &word[idx + 2..]);
+                elements.push((Some(key), value));
+                continue;
+            }
+        }
+        for value in expand_words(shell, &[word]) {
+            elements.push((None, value));
+        }
+    }
+    Ok(elements)
+}
+
+/// Joins the pieces, escaping the special characters in the quoted ones with a backslash
+fn escape_quoted(pieces: &[Piece], is_special: fn(char) -> bool) -> String {
+    let mut pattern = String::new();
+    for piece in pieces {
+        if piece.quoting == Quoting::Quoted {
+            for c in piece.text.chars() {
+                if is_special(c) {
+                    pattern.push('\\');
+                }
+                pattern.push(c);
+            }
+        } else {
+            pattern.push_str(&piece.text);
+        }
+    }
+    pattern
+}
+
+fn is_regex_special(c: char) -> bool {
+    "\\.[]()*+?{}|^$".contains(c)
+}
+
+/// Splits the results of unquoted expansions in the field at the characters of `ifs`.
+/// A run of `ifs` whitespace separates fields, and so does each other `ifs` character together
+/// with the whitespace around it. A field left empty by an unquoted expansion is removed.
+fn split_field(ifs: &str, field: Vec<Piece>) -> Vec<Vec<Piece>> {
+    let mut fields = Vec::new();
+    let mut current = Vec::new();
+    // The current field has text or quotes, so it is kept even if it is empty
+    let mut started = false;
+    // The last field was ended by whitespace, which joins with a following delimiter
+    let mut after_space = false;
+    for piece in field {
+        if piece.quoting != Quoting::Expanded {
+            append(&mut current, &piece.text, piece.quoting);
+            started = true;
+            after_space = false;
+            continue;
+        }
+        for c in piece.text.chars() {
+            if !ifs.contains(c) {
+                let mut buf = [0; 4];
+                append(&mut current, c.encode_utf8(&mut buf), Quoting::Expanded);
+                started = true;
+                after_space = false;
+            } else if c.is_whitespace() {
+                if started {
+                    fields.push(mem::replace(&mut current, Vec::new()));
+                    started = false;
+                    after_space = true;
+                }
+            } else {
+                if started || !after_space {
+                    fields.push(mem::replace(&mut current, Vec::new()));
+                }
+                started = false;
+                after_space = false;
+            }
+        }
+    }
+    if started {
+        fields.push(current);
+    }
+    fields
+}
+
+/// Appends text to the pieces, merging it with the last piece if possible
+fn append(pieces: &mut Vec<Piece>, text: &str, quoting: Quoting) {
+    if let Some(last) = pieces.last_mut() {
+        if last.quoting == quoting {
+            last.text.push_str(text);
+            return;
+        }
+    }
+    pieces.push(Piece {
+        text: text.to_string(),
+        quoting,
+    });
+}
+
+/// Replaces a word having unquoted glob characters with the matching paths,
+/// the word is kept as it is if nothing matches
+fn expand_pathname(pieces: &[Piece]) -> Vec<String> {
+    let text: String = pieces.iter().map(|piece| piece.text.as_str()).collect();
+    let has_glob = pieces.iter()
+        .filter(|piece| piece.quoting != Quoting::Quoted)
+        .any(|piece| piece.text.contains(|c| c == '*' || c == '?' || c == '['));
+    if !has_glob {
+        return vec![text];
+    }
+    let matches = glob::expand_path(&escape_quoted(pieces, glob::is_special));
+    if matches.is_empty() {
+        vec![text]
+    } else {
+        matches
+    }
+}
+
+struct Expander<'s, 'w> {
+    shell: &'s mut Shell,
+    chars: Peekable<Chars<'w>>,
+    /// Finished fields, when an expansion like `"${arr[@]}"` produces more than one
+    fields: Vec<Vec<Piece>>,
+    /// Pieces of the current field
+    pieces: Vec<Piece>,
+    /// Set when `${arr[@]}` of an empty array is expanded, so that a word without
+    /// any other text produces no fields
+    vanish: bool,
+    /// Set for arithmetic expressions
+    arithmetic: bool,
+}
+
+impl<'s, 'w> Expander<'s, 'w> {
+    fn new(shell: &'s mut Shell, word: &'w str) -> Self {
+        Expander {
+            shell,
+            chars: word.chars().peekable(),
+            fields: Vec::new(),
+            pieces: Vec::new(),
+            vanish: false,
+            arithmetic: false,
+        }
+    }
+
+    fn expand(mut self) -> Vec<Vec<Piece>> {
+        if !self.arithmetic {
+            self.tilde();
+            self.process_sub();
+        }
+        while let Some(c) = self.chars.next() {
+            match c {
+                '\'' => {
+                    let mut text = String::new();
+                    for c in self.chars.by_ref() {
+                        if c == '\'' {
+                            break;
+                        }
+                        text.push(c);
+                    }
+                    self.push(text, Quoting::Quoted);
+                }
+                '"' => self.double_quote(),
+                '\\' => match self.chars.next() {
+                    Some('\n') => {}
+                    Some(c) => self.push(c.to_string(), Quoting::Quoted),
+                    None => self.push("\\".to_string(), Quoting::Unquoted)
+                },
+                '$' => self.dollar(Quoting::Expanded),
+                _ => self.push(c.to_string(), Quoting::Unquoted)
+            }
+        }
+        self.break_field();
+        let empty = self.fields.len() == 1 && self.fields[0].iter().all(|piece| piece.text.is_empty());
+        if self.vanish && empty {
+            self.fields.clear();
+        }
+        self.fields
+    }
+
+    /// Expands a `~` prefix at the start of the word, up to the first `/`, into a directory.
+    /// The prefix is kept as it is if it cannot be expanded or any of it is quoted.
+    fn tilde(&mut self) {
+        if self.chars.peek() != Some(&'~') {
+            return;
+        }
+        let prefix: String = self.chars.clone().skip(1).take_while(|&c| c != '/').collect();
+        if let Some(dir) = dirs::tilde(self.shell, &prefix) {
+            for _ in 0..prefix.chars().count() + 1 {
+                self.chars.next();
+            }
+            self.push(dir, Quoting::Quoted);
+        }
+    }
+
+    /// Replaces a `<(cmd)` or `>(cmd)` at the start of the word with the path of a pipe
+    /// connected to the command, like the lexer they are not recognized inside a word
+    fn process_sub(&mut self) {
+        let mut ahead = self.chars.clone();
+        let input = match (ahead.next(), ahead.next()) {
+            (Some('<'), Some('(')) => true,
+            (Some('>'), Some('(')) => false,
+            _ => return
+        };
+        self.chars = ahead;
+        let inner = self.take_paren();
+        let path = match procsub::substitute(self.shell, &inner, input) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("{}", e);
+                String::new()
+            }
+        };
+        self.push(path, Quoting::Quoted);
+    }
+
+    /// Ends the current field, the next pieces go into a new one
+    fn break_field(&mut self) {
+        let pieces = mem::replace(&mut self.pieces, Vec::new());
+        self.fields.push(pieces);
+    }
+
+    /// Pushes the values of `$@` like expansions, each one is a separate field unless `join` is set,
+    /// in that case they are joined with the first character of `IFS` in quotes
+    fn push_all(&mut self, values: Vec<String>, join: bool, quoting: Quoting) {
+        if values.is_empty() {
+            self.vanish = true;
+        }
+        if join && quoting == Quoting::Quoted {
+            let separator: String = self.shell.vars.get("IFS").unwrap_or(" ").chars().take(1).collect();
+            self.push(values.join(&separator), quoting);
+            return;
+        }
+        for (i, value) in values.into_iter().enumerate() {
+            if i > 0 {
+                self.break_field();
+            }
+            self.push(value, quoting);
+        }
+    }
+
+    /// Appends text to the expansion, merging it with the last piece if possible
+    fn push(&mut self, text: String, quoting: Quoting) {
+        append(&mut self.pieces, &text, quoting);
+    }
+
+    fn double_quote(&mut self) {
+        // Push an empty piece so that `""` is kept as an empty argument
+        self.push(String::new(), Quoting::Quoted);
+        while let Some(c) = self.chars.next() {
+            match c {
+                '"' => break,
+                '\\' => match self.chars.next() {
+                    Some(c @ '$') | Some(c @ '`') | Some(c @ '"') | Some(c @ '\\') => self.push(c.to_string(), Quoting::Quoted),
+                    Some('\n') => {}
+                    Some(c) => self.push(format!("\\{}", c), Quoting::Quoted),
+                    None => self.push("\\".to_string(), Quoting::Quoted)
+                },
+                '$' => self.dollar(Quoting::Quoted),
+                _ => self.push(c.to_string(), Quoting::Quoted)
+            }
+        }
+    }
+
+    /// Expands the parameter after a `$`, `quoting` is used for the expanded text
+    fn dollar(&mut self, quoting: Quoting) {
+        match self.chars.peek().cloned() {
+            Some('{') => {
+                self.chars.next();
+                let inner = self.take_brace();
+                self.braced(&inner, quoting);
+            }
+            Some('(') if self.at_arithmetic() => {
+                self.chars.next();
+                let inner = self.take_paren();
+                let expr = if inner.ends_with(')') { &inner[1..inner.len() - 1] } else { &inner[1..] };
+                let value = self.arithmetic(expr);
+                self.push(value, quoting);
+            }
+            Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if c != '_' && !c.is_ascii_alphanumeric() {
+                        break;
+                    }
+                    name.push(c);
+                    self.chars.next();
+                }
+                let value = self.parameter(&name);
+                self.push(value, quoting);
+            }
+            Some(c) if c.is_ascii_digit() || "?$!#".contains(c) => {
+                self.chars.next();
+                let value = self.parameter(&c.to_string());
+                self.push(value, quoting);
+            }
+            Some(c @ '@') | Some(c @ '*') => {
+                self.chars.next();
+                let values = self.shell.positional.clone();
+                self.push_all(values, c == '*', quoting);
+            }
+            _ => {
+                let literal = if quoting == Quoting::Quoted { Quoting::Quoted } else { Quoting::Unquoted };
+                self.push("$".to_string(), literal);
+            }
+        }
+    }
+
+    /// Takes the text until the matching `}`
+    fn take_brace(&mut self) -> String {
+        let mut inner = String::new();
+        let mut depth = 0;
+        while let Some(c) = self.chars.next() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => break,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            inner.push(c);
+        }
+        inner
+    }
+
+    /// Takes the text until the matching `)`
+    fn take_paren(&mut self) -> String {
+        let mut inner = String::new();
+        let mut depth = 0;
+        while let Some(c) = self.chars.next() {
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 0 => break,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            inner.push(c);
+        }
+        inner
+    }
+
+    /// Returns true at the `((` of a `$((expr))`
+    fn at_arithmetic(&self) -> bool {
+        let mut ahead = self.chars.clone();
+        ahead.next() == Some('(') && ahead.next() == Some('(')
+    }
+
+    /// Evaluates the expression of a `$((...))`, parameters in it are expanded first
+    fn arithmetic(&mut self, expr: &str) -> String {
+        let expr = expand_arith(self.shell, expr);
+        match arith::eval(self.shell, &expr) {
+            Ok(value) => value.to_string(),
+            Err(e) => {
+                eprintln!("{}", e);
+                String::new()
+            }
+        }
+    }
+
+    /// Expands the inside of a `${...}`
+    fn braced(&mut self, inner: &str, quoting: Quoting) {
+        if inner.len() > 1 && inner.starts_with('#') {
+            let len = self.length(&inner[1..]);
+            self.push(len.to_string(), quoting);
+            return;
+        }
+        if inner.len() > 1 && inner.starts_with('!') {
+            if let Some((name, subscript)) = split_subscript(&inner[1..]) {
+                if subscript == "@" || subscript == "*" {
+                    let keys = self.shell.vars.get_var(name)
+                        .and_then(|var| var.value.as_ref())
+                        .map_or_else(Vec::new, |value| value.keys());
+                    self.push_all(keys, subscript == "*", quoting);
+                    return;
+                }
+            }
+        }
+        if inner == "@" || inner == "*" {
+            let values = self.shell.positional.clone();
+            self.push_all(values, inner == "*", quoting);
+            return;
+        }
+        match split_subscript(inner) {
+            Some((name, subscript)) if subscript == "@" || subscript == "*" => {
+                let values = self.values(name);
+                self.push_all(values, subscript == "*", quoting);
+            }
+            Some((name, subscript)) => {
+                let value = self.element(name, subscript);
+                self.push(value, quoting);
+            }
+            None => {
+                let value = self.parameter(inner);
+                self.push(value, quoting);
+            }
+        }
+    }
+
+    /// Returns the length of `${#param}`, the number of elements for `${#arr[@]}`
+    fn length(&mut self, param: &str) -> usize {
+        match split_subscript(param) {
+            Some((name, subscript)) if subscript == "@" || subscript == "*" => self.values(name).len(),
+            Some((name, subscript)) => self.element(name, subscript).chars().count(),
+            None if param == "@" || param == "*" => self.shell.positional.len(),
+            None => self.parameter(param).chars().count()
+        }
+    }
+
+    fn values(&self, name: &str) -> Vec<String> {
+        self.shell.vars.get_var(name)
+            .and_then(|var| var.value.as_ref())
+            .map_or_else(Vec::new, |value| value.values().into_iter().map(String::from).collect())
+    }
+
+    fn element(&mut self, name: &str, subscript: &str) -> String {
+        let subscript = expand_word(self.shell, subscript);
+        match self.shell.element(name, &subscript) {
+            Ok(value) => value.unwrap_or_default(),
+            Err(e) => {
+                eprintln!("{}", e);
+                String::new()
+            }
+        }
+    }
+
+    fn parameter(&mut self, name: &str) -> String {
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_digit()) {
+            return match name.parse::<usize>() {
+                Ok(0) => self.shell.name.clone(),
+                Ok(n) => self.shell.positional.get(n - 1).cloned().unwrap_or_default(),
+                _ => String::new()
+            };
+        }
+        match name {
+            "?" => return self.shell.last_status.unwrap_or(1).to_string(),
+            "$" => return self.shell.pid.to_string(),
+            "!" => return self.shell.last_background.map_or_else(String::new, |pid| pid.to_string()),
+            "#" => return self.shell.positional.len().to_string(),
+            "_" => return self.shell.last_arg.clone(),
+            _ => {}
+        }
+        if !is_valid_name(name) {
+            eprintln!("${{{}}}: bad substitution", name);
+            return String::new();
+        }
+        self.shell.vars.get(name).unwrap_or("").to_string()
+    }
+}
+
+/// Splits `name[subscript]` into its parts, returns None if the parameter has no subscript
+fn split_subscript(param: &str) -> Option<(&str, &str)> {
+    if !param.ends_with(']') {
+        return None;
+    }
+    let idx = param.find('[')?;
+    let name = &param[..idx];
+    if is_valid_name(name) {
+        Some((name, &param[idx + 1..param.len() - 1]))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn piece(text: &str, quoting: Quoting) -> Piece {
+        Piece {
+            text: text.to_string(),
+            quoting,
+        }
+    }
+
+    fn split(ifs: &str, field: Vec<Piece>) -> Vec<String> {
+        split_field(ifs, field).iter()
+            .map(|field| field.iter().map(|piece| piece.text.as_str()).collect())
+            .collect()
+    }
+
+    fn split_expanded(ifs: &str, text: &str) -> Vec<String> {
+        split(ifs, vec![piece(text, Quoting::Expanded)])
+    }
+
+    #[test]
+    fn whitespace_runs() {
+        assert_eq!(split_expanded(DEFAULT_IFS, "  a \t b\n\nc  "), vec!["a", "b", "c"]);
+        assert_eq!(split_expanded(" ", "  a  b "), vec!["a", "b"]);
+        assert!(split_expanded(DEFAULT_IFS, " \t ").is_empty());
+    }
+
+    #[test]
+    fn non_whitespace_delimiters() {
+        assert_eq!(split_expanded(":", ":a::b:"), vec!["", "a", "", "b"]);
+        assert_eq!(split_expanded(":", "a:"), vec!["a"]);
+    }
+
+    #[test]
+    fn mixed_delimiters() {
+        assert_eq!(split_expanded(" :", " : a"), vec!["", "a"]);
+        assert_eq!(split_expanded(" :", "a : b"), vec!["a", "b"]);
+        assert_eq!(split_expanded(" :", "a :: b"), vec!["a", "", "b"]);
+        assert_eq!(split_expanded(" :", "a: :b:"), vec!["a", "", "b"]);
+        assert_eq!(split_expanded(" ,", "a , , b"), vec!["a", "", "b"]);
+    }
+
+    #[test]
+    fn empty_ifs() {
+        assert_eq!(split_expanded("", "a b"), vec!["a b"]);
+    }
+
+    #[test]
+    fn only_expansions_are_split() {
+        let field = vec![piece("x", Quoting::Unquoted), piece(" y z", Quoting::Expanded), piece(" w", Quoting::Quoted)];
+        assert_eq!(split(DEFAULT_IFS, field), vec!["x", "y", "z w"]);
+        assert_eq!(split(DEFAULT_IFS, vec![piece("a b", Quoting::Quoted)]), vec!["a b"]);
+        assert_eq!(split(DEFAULT_IFS, vec![piece("", Quoting::Quoted)]), vec![""]);
+        assert!(split(DEFAULT_IFS, vec![piece("", Quoting::Expanded)]).is_empty());
+    }
+
+    #[test]
+    fn array_expansions() {
+        let mut shell = Shell::new();
+        let elements = vec![(None, "a b".to_string()), (Some("5".to_string()), "c".to_string())];
+        shell.assign_array("arr", elements).unwrap();
+        assert_eq!(expand_word(&mut shell, "${#arr[@]} ${#arr[*]} ${#arr[0]}"), "2 2 3");
+        assert_eq!(expand_word(&mut shell, "${!arr[@]}"), "0 5");
+        assert_eq!(expand_word(&mut shell, "$arr ${arr[5]} ${arr[-1]}"), "a b c c");
+        assert_eq!(expand_words(&mut shell, &["\"${arr[@]}\"".to_string()]), vec!["a b", "c"]);
+        assert_eq!(expand_words(&mut shell, &["${arr[@]}".to_string()]), vec!["a", "b", "c"]);
+        assert_eq!(expand_words(&mut shell, &["\"${!arr[@]}\"".to_string()]), vec!["0", "5"]);
+        assert!(expand_words(&mut shell, &["${!missing[@]}".to_string()]).is_empty());
+        assert_eq!(expand_word(&mut shell, "${#missing[@]}"), "0");
+    }
+}
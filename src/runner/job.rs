@@ -0,0 +1,105 @@
+/*
+ * Project: rusth
+ * File: runner/job.rs
+ * Copyright 2018 Caner Korkmaz (Kausta) <info@canerkorkmaz.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+#[cfg(unix)]
+pub use self::unix::*;
+
+#[cfg(unix)]
+mod unix {
+    use std::io::{self, Write};
+    use libc;
+
+    pub type Pid = libc::pid_t;
+
+    /// Runs `f` in a forked copy of the shell, the child exits with the code returned by `f`
+    pub fn fork<F: FnOnce() -> Option<i32>>(f: F) -> io::Result<Pid> {
+        flush_std();
+        match unsafe { libc::fork() } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => {
+                let code = f();
+                flush_std();
+                unsafe { libc::_exit(code.unwrap_or(1)) }
+            }
+            pid => Ok(pid)
+        }
+    }
+
+    /// Waits for the child to finish and returns its exit code, 128 + signal number if it was killed
+    pub fn wait(pid: Pid) -> Option<i32> {
+        let mut status = 0;
+        loop {
+            let res = unsafe { libc::waitpid(pid, &mut status, 0) };
+            if res == pid {
+                break;
+            }
+            let err = io::Error::last_os_error();
+            if res == -1 && err.kind() != io::ErrorKind::Interrupted {
+                eprintln!("Cannot wait for {}: {}", pid, err);
+                return None;
+            }
+        }
+        if libc::WIFEXITED(status) {
+            Some(libc::WEXITSTATUS(status))
+        } else if libc::WIFSIGNALED(status) {
+            Some(128 + libc::WTERMSIG(status))
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the child has finished, without waiting for it
+    pub fn finished(pid: Pid) -> bool {
+        let mut status = 0;
+        match unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) } {
+            0 => false,
+            // The child is either reaped now or cannot be waited for at all
+            _ => true
+        }
+    }
+
+    /// Creates a pipe, returns the read and the write end
+    pub fn pipe() -> io::Result<(i32, i32)> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((fds[0], fds[1]))
+    }
+
+    /// Moves `fd` onto `target`, closing the original
+    pub fn move_fd(fd: i32, target: i32) {
+        if fd != target {
+            unsafe {
+                libc::dup2(fd, target);
+                libc::close(fd);
+            }
+        }
+    }
+
+    pub fn close(fd: i32) {
+        unsafe {
+            libc::close(fd);
+        }
+    }
+
+    fn flush_std() {
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+    }
+}
@@ -17,13 +17,79 @@
 */
 
 pub mod command;
+pub mod shell;
+pub mod vars;
+use std::io;
+
+use parser::{self, ParseError};
 use self::command::*;
+use self::shell::Shell;
 
 mod executable;
 mod builtin;
+mod expand;
+mod arith;
+mod dirs;
+mod redirect;
+mod job;
+mod procsub;
+mod printf;
+mod read;
+mod test;
 #[cfg(windows)]
 mod windows;
 
-pub fn run_command(cmd: &mut Runnable) -> Option<i32> {
-    cmd.run(RunConfig::default())
+pub fn run_command(shell: &mut Shell, cmd: &Runnable) -> Option<i32> {
+    cmd.run(shell, RunConfig::default())
+}
+
+/// Runs commands read line by line, like the ones in a script, and returns the status
+/// of the last one. Syntax errors are reported with `name` and the line number,
+/// and stop the rest from running with status 2.
+pub fn run_lines<I>(shell: &mut Shell, name: &str, lines: I) -> Option<i32>
+    where I: IntoIterator<Item=io::Result<String>> {
+    let mut code = Some(0);
+    // Lines of a command that needs more input, such as an unterminated if
+    let mut pending = String::new();
+    for (i, line) in lines.into_iter().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                return Some(1);
+            }
+        };
+        pending.push_str(&line);
+        match parser::parse(&pending) {
+            Ok(parsed) => {
+                pending.clear();
+                code = run_command(shell, &parsed);
+                if let Some(exit_code) = shell.exit_requested() {
+                    return exit_code;
+                }
+                // `return` from a sourced file, handled by `source`
+                if shell.control.is_some() {
+                    return code;
+                }
+            }
+            Err(ParseError::Incomplete(_)) => pending.push('\n'),
+            Err(e) => {
+                eprintln!("{}:{}: {}", name, i + 1, e);
+                return Some(2);
+            }
+        }
+    }
+    if !pending.is_empty() {
+        if let Err(e) = parser::parse(&pending) {
+            eprintln!("{}: {}", name, e);
+            return Some(2);
+        }
+    }
+    code
+}
+
+/// Runs a script read from the standard input, see `run_lines`. It is read without buffering
+/// so commands like `read` get the input that comes after the line being run.
+pub fn run_stdin(shell: &mut Shell, name: &str) -> Option<i32> {
+    run_lines(shell, name, read::StdinLines)
 }
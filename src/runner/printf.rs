@@ -0,0 +1,511 @@
+/*
+ * Project: rusth
+ * File: runner/printf.rs
+ * Copyright 2018 Caner Korkmaz (Kausta) <info@canerkorkmaz.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use super::arith::parse_number;
+
+/// Replaces the backslash escapes of `echo -e` and `%b`, octal escapes are written `\0NNN`.
+/// Returns the bytes, since escapes can give bytes that are not valid UTF-8,
+/// and true if it ended early because of `\c`.
+pub fn echo_escapes(text: &str) -> (Vec<u8>, bool) {
+    unescape(text, true)
+}
+
+/// Replaces the backslash escapes, octal ones are `\0NNN` if `echo` is set and `\NNN` otherwise
+fn unescape(text: &str, echo: bool) -> (Vec<u8>, bool) {
+    let mut res = Vec::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            push_char(&mut res, c);
+            continue;
+        }
+        match chars.next() {
+            Some('a') => res.push(0x07),
+            Some('b') => res.push(0x08),
+            Some('c') if echo => return (res, true),
+            Some('e') | Some('E') => res.push(0x1b),
+            Some('f') => res.push(0x0c),
+            Some('n') => res.push(b'\n'),
+            Some('r') => res.push(b'\r'),
+            Some('t') => res.push(b'\t'),
+            Some('v') => res.push(0x0b),
+            Some('\\') => res.push(b'\\'),
+            Some('0') if echo => res.push(take_code(&mut chars, 8, 3)),
+            Some(c @ '0'..='7') if !echo => {
+                let rest = take_digits(&mut chars, 8, 2);
+                res.push(byte_code(&format!("{}{}", c, rest), 8));
+            }
+            Some('x') if chars.peek().map_or(false, |c| c.is_digit(16)) => res.push(take_code(&mut chars, 16, 2)),
+            Some('"') if !echo => res.push(b'"'),
+            Some('\'') if !echo => res.push(b'\''),
+            Some(c) => {
+                res.push(b'\\');
+                push_char(&mut res, c);
+            }
+            None => res.push(b'\\')
+        }
+    }
+    (res, false)
+}
+
+fn push_char(res: &mut Vec<u8>, c: char) {
+    let mut buf = [0; 4];
+    res.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+}
+
+fn take_digits(chars: &mut Peekable<Chars>, radix: u32, max: usize) -> String {
+    let mut digits = String::new();
+    while digits.len() < max {
+        match chars.peek() {
+            Some(&c) if c.is_digit(radix) => digits.push(c),
+            _ => break
+        }
+        chars.next();
+    }
+    digits
+}
+
+fn take_code(chars: &mut Peekable<Chars>, radix: u32, max: usize) -> u8 {
+    let digits = take_digits(chars, radix, max);
+    byte_code(&digits, radix)
+}
+
+/// Returns the byte written by a numeric escape, values above 255 keep their low byte
+fn byte_code(digits: &str, radix: u32) -> u8 {
+    (u32::from_str_radix(digits, radix).unwrap_or(0) & 0xff) as u8
+}
+
+/// A `%` directive of a format, like `%-8.3s`
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: Option<usize>,
+    precision: Option<usize>,
+}
+
+/// Formats the arguments like `printf`, the format is reused until all of them are consumed.
+/// Returns the output and false if some argument was not a valid number.
+pub fn format(format: &str, args: &[String]) -> (Vec<u8>, bool) {
+    let mut formatter = Formatter {
+        args,
+        next: 0,
+        valid: true,
+    };
+    let mut res = Vec::new();
+    loop {
+        let start = formatter.next;
+        // `\c` in a `%b` argument stops the output
+        if formatter.format_once(format, &mut res) {
+            break;
+        }
+        if formatter.next >= args.len() || formatter.next == start {
+            break;
+        }
+    }
+    (res, formatter.valid)
+}
+
+struct Formatter<'a> {
+    args: &'a [String],
+    next: usize,
+    valid: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn next_arg(&mut self) -> Option<&'a str> {
+        let arg = self.args.get(self.next).map(|arg| arg.as_str());
+        self.next += 1;
+        arg
+    }
+
+    /// Returns the next argument as an integer, missing arguments are 0
+    fn next_int(&mut self) -> i64 {
+        match self.next_arg() {
+            Some(arg) => self.parse_int(arg),
+            None => 0
+        }
+    }
+
+    fn parse_int(&mut self, arg: &str) -> i64 {
+        // A leading quote gives the code of the character after it
+        if arg.starts_with('\'') || arg.starts_with('"') {
+            return arg.chars().nth(1).map_or(0, |c| c as i64);
+        }
+        let trimmed = arg.trim();
+        let (negative, digits) = if trimmed.starts_with('-') {
+            (true, &trimmed[1..])
+        } else {
+            (false, trimmed.trim_start_matches('+'))
+        };
+        match parse_number(digits) {
+            Ok(value) => if negative { value.wrapping_neg() } else { value },
+            Err(_) => {
+                eprintln!("printf: {}: invalid number", arg);
+                self.valid = false;
+                0
+            }
+        }
+    }
+
+    fn next_float(&mut self) -> f64 {
+        match self.next_arg() {
+            Some(arg) if arg.starts_with('\'') || arg.starts_with('"') => arg.chars().nth(1).map_or(0.0, |c| c as u32 as f64),
+            Some(arg) => match arg.trim().parse() {
+                Ok(value) => value,
+                Err(_) => {
+                    eprintln!("printf: {}: invalid number", arg);
+                    self.valid = false;
+                    0.0
+                }
+            },
+            None => 0.0
+        }
+    }
+
+    /// Formats the whole format once, returns true if the output stopped at `\c`
+    fn format_once(&mut self, format: &str, res: &mut Vec<u8>) -> bool {
+        let mut chars = format.chars().peekable();
+        let mut literal = String::new();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                literal.push(c);
+                if let Some(c) = chars.next() {
+                    literal.push(c);
+                }
+                continue;
+            }
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            res.extend(unescape(&literal, false).0);
+            literal.clear();
+            if chars.peek() == Some(&'%') {
+                chars.next();
+                res.push(b'%');
+                continue;
+            }
+            let spec = self.parse_spec(&mut chars);
+            let conversion = match chars.next() {
+                Some(conversion) => conversion,
+                None => {
+                    res.push(b'%');
+                    break;
+                }
+            };
+            if self.convert(conversion, &spec, res) {
+                return true;
+            }
+        }
+        res.extend(unescape(&literal, false).0);
+        false
+    }
+
+    fn parse_spec(&mut self, chars: &mut Peekable<Chars>) -> Spec {
+        let mut spec = Spec::default();
+        while let Some(&c) = chars.peek() {
+            match c {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alternate = true,
+                '0' => spec.zero = true,
+                _ => break
+            }
+            chars.next();
+        }
+        if chars.peek() == Some(&'*') {
+            chars.next();
+            let width = self.next_int();
+            if width < 0 {
+                spec.left = true;
+            }
+            spec.width = self.field_size(width);
+        } else {
+            spec.width = take_digits(chars, 10, usize::max_value()).parse::<i32>().ok().map(|width| width as usize);
+        }
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            if chars.peek() == Some(&'*') {
+                chars.next();
+                let precision = self.next_int().max(0);
+                spec.precision = Some(self.field_size(precision).unwrap_or(0));
+            } else {
+                spec.precision = Some(take_digits(chars, 10, usize::max_value()).parse::<i32>().unwrap_or(0) as usize);
+            }
+        }
+        spec
+    }
+
+    /// Returns the size of a `*` width or precision, which has to fit in an `int` like in C
+    fn field_size(&mut self, value: i64) -> Option<usize> {
+        let size = value.unsigned_abs();
+        if size > i32::MAX as u64 {
+            eprintln!("printf: {}: Numerical result out of range", value);
+            self.valid = false;
+            return None;
+        }
+        Some(size as usize)
+    }
+
+    /// Formats the next argument, returns true if the output stopped at `\c`
+    fn convert(&mut self, conversion: char, spec: &Spec, res: &mut Vec<u8>) -> bool {
+        let text = match conversion {
+            's' => {
+                let arg = self.next_arg().unwrap_or("");
+                match spec.precision {
+                    Some(precision) => pad(arg.chars().take(precision).collect::<String>().as_bytes(), spec),
+                    None => pad(arg.as_bytes(), spec)
+                }
+            }
+            'b' => {
+                let (text, stop) = unescape(self.next_arg().unwrap_or(""), true);
+                res.extend(pad(&text, spec));
+                return stop;
+            }
+            'q' => pad(shell_quote(self.next_arg().unwrap_or("")).as_bytes(), spec),
+            'c' => pad(self.next_arg().and_then(|arg| arg.chars().next()).map_or(String::new(), |c| c.to_string()).as_bytes(), spec),
+            'd' | 'i' => {
+                let value = self.next_int();
+                let digits = with_precision(value.unsigned_abs().to_string(), spec);
+                let sign = if value < 0 { "-" } else if spec.plus { "+" } else if spec.space { " " } else { "" };
+                number(sign, &digits, spec)
+            }
+            'u' | 'o' | 'x' | 'X' => {
+                let value = self.next_int() as u64;
+                let digits = match conversion {
+                    'u' => value.to_string(),
+                    'o' => format!("{:o}", value),
+                    'x' => format!("{:x}", value),
+                    _ => format!("{:X}", value)
+                };
+                let digits = with_precision(digits, spec);
+                let prefix = match conversion {
+                    'o' if spec.alternate && !digits.starts_with('0') => "0",
+                    'x' if spec.alternate && value != 0 => "0x",
+                    'X' if spec.alternate && value != 0 => "0X",
+                    _ => ""
+                };
+                number(prefix, &digits, spec)
+            }
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                let value = self.next_float();
+                let digits = format_float(value.abs(), conversion, spec);
+                let sign = if value.is_sign_negative() && value != 0.0 { "-" } else if spec.plus { "+" } else if spec.space { " " } else { "" };
+                number(sign, &digits, spec)
+            }
+            _ => {
+                eprintln!("printf: %{}: invalid format character", conversion);
+                self.valid = false;
+                return false;
+            }
+        };
+        res.extend(text);
+        false
+    }
+}
+
+/// Pads a number with zeros up to the precision, which is the minimum number of digits
+fn with_precision(digits: String, spec: &Spec) -> String {
+    match spec.precision {
+        Some(precision) if digits.len() < precision => format!("{}{}", "0".repeat(precision - digits.len()), digits),
+        _ => digits
+    }
+}
+
+/// Joins a number with its sign or prefix and pads it, zero padding goes between them
+fn number(prefix: &str, digits: &str, spec: &Spec) -> Vec<u8> {
+    let zero = spec.zero && !spec.left && (spec.precision.is_none() || digits.contains('.'));
+    let len = prefix.chars().count() + digits.chars().count();
+    match spec.width {
+        Some(width) if zero && width > len => format!("{}{}{}", prefix, "0".repeat(width - len), digits).into_bytes(),
+        _ => pad(format!("{}{}", prefix, digits).as_bytes(), spec)
+    }
+}
+
+/// Pads the text with spaces up to the width, counting characters, or single bytes where it is not UTF-8
+fn pad(text: &[u8], spec: &Spec) -> Vec<u8> {
+    let len = String::from_utf8_lossy(text).chars().count();
+    let mut res = Vec::with_capacity(text.len());
+    match spec.width {
+        Some(width) if width > len => {
+            let fill = " ".repeat(width - len);
+            if spec.left {
+                res.extend_from_slice(text);
+                res.extend_from_slice(fill.as_bytes());
+            } else {
+                res.extend_from_slice(fill.as_bytes());
+                res.extend_from_slice(text);
+            }
+        }
+        _ => res.extend_from_slice(text)
+    }
+    res
+}
+
+/// Formats a positive float for `%f`, `%e` and `%g`
+fn format_float(value: f64, conversion: char, spec: &Spec) -> String {
+    let precision = spec.precision.unwrap_or(6);
+    let res = match conversion {
+        'f' | 'F' => format!("{:.*}", precision, value),
+        'e' | 'E' => exponent(value, precision),
+        _ => {
+            let precision = if precision == 0 { 1 } else { precision };
+            let exp = if value == 0.0 { 0 } else { value.log10().floor() as i32 };
+            let res = if exp < -4 || exp >= precision as i32 {
+                exponent(value, precision - 1)
+            } else {
+                format!("{:.*}", (precision as i32 - 1 - exp).max(0) as usize, value)
+            };
+            if spec.alternate {
+                res
+            } else {
+                strip_zeros(&res)
+            }
+        }
+    };
+    if conversion.is_uppercase() {
+        res.to_uppercase()
+    } else {
+        res
+    }
+}
+
+/// Formats like `%e`, with at least two digits in the exponent, `1.500000e+02`
+fn exponent(value: f64, precision: usize) -> String {
+    let formatted = format!("{:.*e}", precision, value);
+    let idx = formatted.find('e').unwrap_or(formatted.len());
+    let (mantissa, exp) = formatted.split_at(idx);
+    let exp: i32 = exp[1..].parse().unwrap_or(0);
+    format!("{}e{}{:02}", mantissa, if exp < 0 { '-' } else { '+' }, exp.abs())
+}
+
+/// Removes the trailing zeros after the decimal point of `%g`
+fn strip_zeros(number: &str) -> String {
+    let (mantissa, exp) = match number.find('e') {
+        Some(idx) => number.split_at(idx),
+        None => (number, "")
+    };
+    let mantissa = if mantissa.contains('.') {
+        mantissa.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        mantissa
+    };
+    format!("{}{}", mantissa, exp)
+}
+
+/// Quotes the text so that it can be read back by the shell as a single word
+fn shell_quote(text: &str) -> String {
+    if text.is_empty() {
+        return "''".to_string();
+    }
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\n' => res.push_str("$'\\n'"),
+            '\t' => res.push_str("$'\\t'"),
+            c if c.is_alphanumeric() || "_./:,=+@%^-".contains(c) => res.push(c),
+            c => {
+                res.push('\\');
+                res.push(c);
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printf(fmt: &str, args: &[&str]) -> (Vec<u8>, bool) {
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        format(fmt, &args)
+    }
+
+    fn output(fmt: &str, args: &[&str]) -> String {
+        let (output, valid) = printf(fmt, args);
+        assert!(valid, "{} was not valid", fmt);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(output("a\\tb\\n", &[]), "a\tb\n");
+        assert_eq!(output("\\101\\x41\\'\\\"\\q", &[]), "AA'\"\\q");
+        assert_eq!(output("%b", &["\\0101\\x41\\c"]), "AA");
+        assert_eq!(output("%b|%s", &["\\101", "\\n"]), "\\101|\\n");
+        assert_eq!(output("[%5b]", &["\\t"]), "[    \t]");
+        assert_eq!(echo_escapes("a\\cb"), (b"a".to_vec(), true));
+        assert_eq!(echo_escapes("\\e\\0\\x"), (b"\x1b\0\\x".to_vec(), false));
+    }
+
+    #[test]
+    fn raw_bytes() {
+        assert_eq!(printf("\\xff\\200", &[]).0, vec![0xff, 0x80]);
+        assert_eq!(printf("%b", &["\\0377\\x80"]).0, vec![0xff, 0x80]);
+        assert_eq!(printf("[%3b]", &["\\xe9"]).0, b"[  \xe9]".to_vec());
+        assert_eq!(echo_escapes("é\\xe9").0, vec![0xc3, 0xa9, 0xe9]);
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(output("%5s|%-5s|%.2s", &["ab", "cd", "xyz"]), "   ab|cd   |xy");
+        assert_eq!(output("%d %i %+d % d", &["42", "-7", "3", "3"]), "42 -7 +3  3");
+        assert_eq!(output("%05d|%-4d|%.3d", &["-42", "7", "5"]), "-0042|7   |005");
+        assert_eq!(output("%x %X %#o %#x %u", &["255", "255", "8", "0", "-1"]), "ff FF 010 0 18446744073709551615");
+        assert_eq!(output("%d %d", &["'A", "0x10"]), "65 16");
+        assert_eq!(output("%.2f|%8.3e|%g", &["3.14159", "1234.5", "0.0001"]), "3.14|1.234e+03|0.0001");
+        assert_eq!(output("%c%c", &["hello", "é"]), "hé");
+        assert_eq!(output("%*d|%-*d|%.*d", &["4", "1", "-3", "2", "2", "3"]), "   1|2  |03");
+        assert_eq!(output("%%|%s|%d", &[]), "%||0");
+    }
+
+    #[test]
+    fn reuses_format() {
+        assert_eq!(output("%s=%d\n", &["a", "1", "b"]), "a=1\nb=0\n");
+        assert_eq!(output("%s %s\n", &["a", "b", "c", "d"]), "a b\nc d\n");
+        assert_eq!(output("x\n", &["ignored"]), "x\n");
+        assert_eq!(output("%s%b\n", &["a", "\\cb", "c"]), "a");
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(output("%d", &["-9223372036854775808"]), "-9223372036854775808");
+        assert_eq!(output("%5d|% d", &["-9223372036854775808", "-9223372036854775808"]),
+            "-9223372036854775808|-9223372036854775808");
+        assert_eq!(printf("[%*d]", &["9223372036854775807", "1"]), (b"[1]".to_vec(), false));
+        assert_eq!(printf("[%*d]", &["-9223372036854775808", "1"]), (b"[1]".to_vec(), false));
+        assert_eq!(printf("[%.*d]", &["4294967296", "1"]), (b"[1]".to_vec(), false));
+        assert_eq!(output("[%99999999999d]", &["1"]), "[1]");
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(printf("%d|%d|%d", &["x", "1", "0x"]), (b"0|1|0".to_vec(), false));
+        assert_eq!(printf("%z", &["1"]).1, false);
+        assert_eq!(output("abc%", &[]), "abc%");
+    }
+}
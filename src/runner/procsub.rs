@@ -0,0 +1,97 @@
+/*
+ * Project: rusth
+ * File: runner/procsub.rs
+ * Copyright 2018 Caner Korkmaz (Kausta) <info@canerkorkmaz.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use super::shell::Shell;
+
+/// A running `<(cmd)` or `>(cmd)`, the shell keeps its end of the pipe open
+/// until the command using it finishes
+#[derive(Debug, Clone)]
+pub struct ProcessSub {
+    #[cfg(unix)]
+    pid: sys::Pid,
+    #[cfg(unix)]
+    fd: i32,
+}
+
+/// Starts `cmd` connected to a pipe and returns a path to the other end of it,
+/// `input` is true for `<(cmd)`, where the output of `cmd` is read from the path
+pub fn substitute(shell: &mut Shell, cmd: &str, input: bool) -> Result<String, String> {
+    sys::substitute(shell, cmd, input)
+}
+
+/// Closes the pipes of the substitutions started after `mark` and waits for their commands
+pub fn finish(shell: &mut Shell, mark: usize) {
+    if shell.process_subs.len() > mark {
+        for sub in shell.process_subs.split_off(mark) {
+            sys::finish(sub);
+        }
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use parser;
+    use runner::job;
+    use runner::shell::Shell;
+    use super::ProcessSub;
+
+    pub use runner::job::Pid;
+
+    pub fn substitute(shell: &mut Shell, cmd: &str, input: bool) -> Result<String, String> {
+        let parsed = parser::parse(cmd).map_err(|e| format!("{}: {}", cmd, e))?;
+        let (read, write) = job::pipe().map_err(|e| format!("Cannot create a pipe: {}", e))?;
+        let (ours, theirs, target) = if input { (read, write, 1) } else { (write, read, 0) };
+        let pid = job::fork(|| {
+            job::close(ours);
+            job::move_fd(theirs, target);
+            // Pipes of the other substitutions must not be kept open by this one
+            for sub in shell.process_subs.drain(..) {
+                job::close(sub.fd);
+            }
+            shell.run_in_child(&parsed)
+        });
+        job::close(theirs);
+        match pid {
+            Ok(pid) => {
+                shell.process_subs.push(ProcessSub { pid, fd: ours });
+                Ok(format!("/dev/fd/{}", ours))
+            }
+            Err(e) => {
+                job::close(ours);
+                Err(format!("Cannot fork: {}", e))
+            }
+        }
+    }
+
+    pub fn finish(sub: ProcessSub) {
+        job::close(sub.fd);
+        job::wait(sub.pid);
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use runner::shell::Shell;
+    use super::ProcessSub;
+
+    pub fn substitute(_shell: &mut Shell, _cmd: &str, _input: bool) -> Result<String, String> {
+        Err("Process substitution is not supported on this platform".to_string())
+    }
+
+    pub fn finish(_sub: ProcessSub) {}
+}
@@ -0,0 +1,414 @@
+/*
+ * Project: rusth
+ * File: runner/read.rs
+ * Copyright 2018 Caner Korkmaz (Kausta) <info@canerkorkmaz.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::io::{self, Write};
+use std::str;
+use std::time::{Duration, Instant};
+
+use super::command::Command;
+use super::expand::DEFAULT_IFS;
+use super::shell::Shell;
+use super::vars::is_valid_name;
+
+/// Status of `read` when the timeout expires
+const TIMEOUT_STATUS: i32 = 142;
+
+#[derive(Default)]
+struct Options {
+    /// Backslashes are kept instead of escaping the next character, `-r`
+    raw: bool,
+    /// The input is not echoed on a terminal, `-s`
+    silent: bool,
+    prompt: Option<String>,
+    timeout: Option<Duration>,
+    /// Number of characters to read at most, `-n`
+    count: Option<usize>,
+    delimiter: u8,
+    /// Name of the array the fields are assigned to, `-a`
+    array: Option<String>,
+}
+
+/// Reads a line from the standard input and assigns its fields split with `IFS` to the names,
+/// the last one gets the rest of the line, without names the whole line goes into `REPLY`
+pub fn read(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let (options, names) = match parse_options(&cmd.args[1..]) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("read: {}", e);
+            return Some(2);
+        }
+    };
+    for name in names.iter().chain(options.array.iter()) {
+        if !is_valid_name(name) {
+            eprintln!("read: `{}': not a valid identifier", name);
+            return Some(1);
+        }
+    }
+
+    // A zero timeout only checks if there is input, without reading it
+    if options.timeout == Some(Duration::from_secs(0)) {
+        return Some(if sys::input_available() { 0 } else { 1 });
+    }
+    let tty = sys::is_tty();
+    if let Some(ref prompt) = options.prompt {
+        if tty {
+            eprint!("{}", prompt);
+            let _ = io::stderr().flush();
+        }
+    }
+    let (chars, mut code) = {
+        // The terminal is changed only while reading, `-n` has to see the characters before a newline
+        let _guard = if tty { sys::set_terminal(options.silent, options.count.is_some()) } else { None };
+        read_input(&options)
+    };
+
+    let line: String = chars.iter().map(|&(c, _)| c).collect();
+    let ifs = shell.vars.get("IFS").unwrap_or(DEFAULT_IFS).to_string();
+    let res = if let Some(ref array) = options.array {
+        let elements = split(&chars, &ifs, None).into_iter().map(|field| (None, field)).collect();
+        shell.assign_array(array, elements)
+    } else if names.is_empty() {
+        shell.assign("REPLY", line)
+    } else {
+        let mut fields = split(&chars, &ifs, Some(names.len())).into_iter();
+        names.iter().map(|name| shell.assign(name, fields.next().unwrap_or_default())).collect()
+    };
+    if let Err(e) = res {
+        eprintln!("read: {}", e);
+        code = Some(1);
+    }
+    code
+}
+
+fn parse_options(args: &[String]) -> Result<(Options, Vec<String>), String> {
+    let mut options = Options {
+        delimiter: b'\n',
+        ..Options::default()
+    };
+    let mut args = args.iter();
+    let mut names = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if !arg.starts_with('-') || arg.len() < 2 {
+            names.push(arg.clone());
+            break;
+        }
+        let mut flags = arg[1..].chars();
+        while let Some(flag) = flags.next() {
+            match flag {
+                'r' => options.raw = true,
+                's' => options.silent = true,
+                'p' | 't' | 'n' | 'd' | 'a' => {
+                    // The value is the rest of the word or the next argument, like `-n1` or `-n 1`
+                    let rest: String = flags.by_ref().collect();
+                    let value = if rest.is_empty() {
+                        args.next().cloned().ok_or_else(|| format!("-{}: option requires an argument", flag))?
+                    } else {
+                        rest
+                    };
+                    match flag {
+                        'p' => options.prompt = Some(value),
+                        't' => match value.parse::<f64>() {
+                            Ok(seconds) if seconds >= 0.0 => {
+                                let millis = (seconds * 1000.0) as u64;
+                                options.timeout = Some(Duration::from_millis(millis));
+                            }
+                            _ => return Err(format!("{}: invalid timeout specification", value))
+                        },
+                        'n' => match value.parse() {
+                            Ok(count) => options.count = Some(count),
+                            Err(_) => return Err(format!("{}: invalid number", value))
+                        },
+                        'd' => options.delimiter = value.bytes().next().unwrap_or(0),
+                        _ => options.array = Some(value)
+                    }
+                }
+                _ => return Err(format!("-{}: invalid option", flag))
+            }
+        }
+    }
+    names.extend(args.cloned());
+    Ok((options, names))
+}
+
+/// Reads up to the delimiter, returns the characters, each one with true if it was escaped
+/// with a backslash, and the status, which is 1 at the end of the input
+fn read_input(options: &Options) -> (Vec<(char, bool)>, Option<i32>) {
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+    let mut chars = Vec::new();
+    // Bytes of a character that is not complete yet
+    let mut pending = Vec::new();
+    let mut escaped = false;
+    loop {
+        if options.count.map_or(false, |count| chars.len() >= count) {
+            return (chars, Some(0));
+        }
+        let byte = match sys::read_byte(deadline) {
+            Ok(Some(byte)) => byte,
+            Ok(None) => return (chars, Some(1)),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => return (chars, Some(TIMEOUT_STATUS)),
+            Err(e) => {
+                eprintln!("read: {}", e);
+                return (chars, Some(1));
+            }
+        };
+        if pending.is_empty() && !escaped {
+            if byte == options.delimiter {
+                return (chars, Some(0));
+            }
+            if byte == b'\\' && !options.raw {
+                escaped = true;
+                continue;
+            }
+        }
+        if escaped && byte == b'\n' {
+            // A backslash before a newline continues the line
+            escaped = false;
+            continue;
+        }
+        pending.push(byte);
+        let decoded = match str::from_utf8(&pending) {
+            Ok(text) => Some(text.to_string()),
+            Err(_) if pending.len() >= 4 => Some(String::from_utf8_lossy(&pending).into_owned()),
+            Err(_) => None
+        };
+        if let Some(text) = decoded {
+            chars.extend(text.chars().map(|c| (c, escaped)));
+            pending.clear();
+            escaped = false;
+        }
+    }
+}
+
+/// Lines of the standard input, read a byte at a time like `read` does, so a script
+/// that comes from the standard input leaves the lines after the current one to its commands
+pub struct StdinLines;
+
+impl Iterator for StdinLines {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        let mut line = Vec::new();
+        loop {
+            match sys::read_byte(None) {
+                Ok(Some(b'\n')) => break,
+                Ok(Some(byte)) => line.push(byte),
+                Ok(None) if line.is_empty() => return None,
+                Ok(None) => break,
+                Err(e) => return Some(Err(e))
+            }
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Some(String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+}
+
+/// Splits the characters into fields with `IFS`, escaped characters never separate fields.
+/// With `max` fields the last one gets the rest of the line without the trailing `IFS` whitespace.
+fn split(chars: &[(char, bool)], ifs: &str, max: Option<usize>) -> Vec<String> {
+    let is_ifs = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c);
+    let is_space = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c) && c.is_whitespace();
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() && is_space(&chars[pos]) {
+        pos += 1;
+    }
+    while pos < chars.len() {
+        if max.map_or(false, |max| fields.len() + 1 == max) {
+            let mut end = chars.len();
+            while end > pos && is_space(&chars[end - 1]) {
+                end -= 1;
+            }
+            fields.push(chars[pos..end].iter().map(|&(c, _)| c).collect());
+            break;
+        }
+        let start = pos;
+        while pos < chars.len() && !is_ifs(&chars[pos]) {
+            pos += 1;
+        }
+        fields.push(chars[start..pos].iter().map(|&(c, _)| c).collect());
+        // A separator is whitespace around at most one other IFS character
+        while pos < chars.len() && is_space(&chars[pos]) {
+            pos += 1;
+        }
+        if pos < chars.len() && is_ifs(&chars[pos]) && !is_space(&chars[pos]) {
+            pos += 1;
+            while pos < chars.len() && is_space(&chars[pos]) {
+                pos += 1;
+            }
+        }
+    }
+    fields
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::io;
+    use std::time::Instant;
+    use libc;
+
+    pub fn is_tty() -> bool {
+        unsafe { libc::isatty(0) == 1 }
+    }
+
+    /// Restores the terminal settings when dropped
+    pub struct TerminalGuard {
+        saved: libc::termios,
+    }
+
+    impl Drop for TerminalGuard {
+        fn drop(&mut self) {
+            unsafe { libc::tcsetattr(0, libc::TCSANOW, &self.saved) };
+        }
+    }
+
+    /// Turns off the echo of the input if `silent`, and line buffering if `raw`
+    pub fn set_terminal(silent: bool, raw: bool) -> Option<TerminalGuard> {
+        if !silent && !raw {
+            return None;
+        }
+        unsafe {
+            let mut term: libc::termios = ::std::mem::zeroed();
+            if libc::tcgetattr(0, &mut term) != 0 {
+                return None;
+            }
+            let guard = TerminalGuard { saved: term };
+            if silent {
+                term.c_lflag &= !libc::ECHO;
+            }
+            if raw {
+                term.c_lflag &= !libc::ICANON;
+                term.c_cc[libc::VMIN] = 1;
+                term.c_cc[libc::VTIME] = 0;
+            }
+            libc::tcsetattr(0, libc::TCSANOW, &term);
+            Some(guard)
+        }
+    }
+
+    /// Returns true if reading would not block, also at the end of the input
+    pub fn input_available() -> bool {
+        let mut fd = libc::pollfd { fd: 0, events: libc::POLLIN, revents: 0 };
+        unsafe { libc::poll(&mut fd, 1, 0) > 0 }
+    }
+
+    /// Reads a single byte so nothing after the line is taken from the input, None at its end
+    pub fn read_byte(deadline: Option<Instant>) -> io::Result<Option<u8>> {
+        loop {
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                let remaining = if deadline > now { deadline - now } else { Default::default() };
+                let millis = remaining.as_secs() * 1000 + u64::from(remaining.subsec_nanos() / 1_000_000);
+                let mut fd = libc::pollfd { fd: 0, events: libc::POLLIN, revents: 0 };
+                let ready = unsafe { libc::poll(&mut fd, 1, millis as libc::c_int) };
+                if ready == 0 {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+                }
+            }
+            let mut byte = 0u8;
+            let res = unsafe { libc::read(0, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+            match res {
+                1 => return Ok(Some(byte)),
+                0 => return Ok(None),
+                _ => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::io::{self, Read};
+    use std::time::Instant;
+
+    pub fn is_tty() -> bool {
+        false
+    }
+
+    pub struct TerminalGuard;
+
+    pub fn set_terminal(_silent: bool, _raw: bool) -> Option<TerminalGuard> {
+        None
+    }
+
+    /// Input is not polled here, reading is assumed not to block
+    pub fn input_available() -> bool {
+        true
+    }
+
+    /// Timeouts are not supported here, the standard input is read until a byte is available
+    pub fn read_byte(_deadline: Option<Instant>) -> io::Result<Option<u8>> {
+        let mut byte = [0u8];
+        match io::stdin().read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split_str(text: &str, ifs: &str, max: Option<usize>) -> Vec<String> {
+        let chars = text.chars().map(|c| (c, false)).collect::<Vec<_>>();
+        split(&chars, ifs, max)
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_eq!(split_str("  a \t b  c ", DEFAULT_IFS, None), vec!["a", "b", "c"]);
+        assert_eq!(split_str("   ", DEFAULT_IFS, None), Vec::<String>::new());
+        assert_eq!(split_str("a b", "", None), vec!["a b"]);
+    }
+
+    #[test]
+    fn mixed_separators() {
+        assert_eq!(split_str("a:b::c", ":", None), vec!["a", "b", "", "c"]);
+        assert_eq!(split_str(":a:", ":", None), vec!["", "a"]);
+        assert_eq!(split_str(" a : b  c ", " :", None), vec!["a", "b", "c"]);
+        assert_eq!(split_str("a : : b", " :", None), vec!["a", "", "b"]);
+        assert_eq!(split_str(" : a", " :", None), vec!["", "a"]);
+        assert_eq!(split_str("a\t,\t,b", "\t,", None), vec!["a", "", "b"]);
+    }
+
+    #[test]
+    fn max_fields() {
+        assert_eq!(split_str("  a  b  c  ", DEFAULT_IFS, Some(2)), vec!["a", "b  c"]);
+        assert_eq!(split_str("a:b:c:", ":", Some(2)), vec!["a", "b:c:"]);
+        assert_eq!(split_str("a b c", DEFAULT_IFS, Some(1)), vec!["a b c"]);
+        assert_eq!(split_str("a", DEFAULT_IFS, Some(3)), vec!["a"]);
+    }
+
+    #[test]
+    fn escaped_separators() {
+        let chars = vec![('a', false), (' ', true), ('b', false), (' ', false), (':', true), ('c', false)];
+        assert_eq!(split(&chars, " :", None), vec!["a b", ":c"]);
+        let chars = vec![(' ', true), ('a', false), (' ', false)];
+        assert_eq!(split(&chars, DEFAULT_IFS, None), vec![" a"]);
+    }
+}
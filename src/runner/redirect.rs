@@ -0,0 +1,201 @@
+/*
+ * Project: rusth
+ * File: runner/redirect.rs
+ * Copyright 2018 Caner Korkmaz (Kausta) <info@canerkorkmaz.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::io::{self, Write};
+
+use super::command::{Redirect, RedirectKind, RunConfig};
+use super::expand::expand_word;
+use super::shell::Shell;
+
+/// Restores the file descriptors replaced by the redirections when dropped
+pub struct RedirectGuard {
+    /// Replaced descriptors with a copy of their old value, None if they were closed
+    saved: Vec<(i32, Option<i32>)>,
+}
+
+impl Drop for RedirectGuard {
+    fn drop(&mut self) {
+        flush_std();
+        for &(fd, saved) in self.saved.iter().rev() {
+            sys::restore(fd, saved);
+        }
+    }
+}
+
+impl RedirectGuard {
+    /// Keeps the redirections instead of restoring the old descriptors
+    pub fn keep(mut self) {
+        flush_std();
+        for (_, saved) in self.saved.drain(..) {
+            sys::discard(saved);
+        }
+    }
+}
+
+/// Applies the redirections to the file descriptors of the shell, so that both builtins
+/// and spawned processes use them, until the returned guard is dropped.
+/// Where descriptors cannot be replaced, the standard input and output of the process
+/// started with `conf` are redirected instead.
+pub fn apply(shell: &mut Shell, redirects: &[Redirect], conf: &mut RunConfig) -> Result<RedirectGuard, String> {
+    let mut guard = RedirectGuard {
+        saved: Vec::new(),
+    };
+    if redirects.is_empty() {
+        return Ok(guard);
+    }
+    flush_std();
+    for redirect in redirects {
+        let target = expand_word(shell, &redirect.target);
+        let source = match redirect.kind {
+            RedirectKind::Dup => Source::Fd(parse_dup_target(&target)?),
+            kind => Source::File(open_file(kind, &target)?)
+        };
+        guard.saved.push((redirect.fd, sys::save(redirect.fd)));
+        sys::replace(redirect.fd, source, conf).map_err(|e| format!("{}: {}", redirect.fd, e))?;
+    }
+    Ok(guard)
+}
+
+/// What a file descriptor is replaced with
+enum Source {
+    File(::std::fs::File),
+    /// Copy of an open file descriptor, None closes it
+    Fd(Option<i32>),
+}
+
+fn parse_dup_target(target: &str) -> Result<Option<i32>, String> {
+    if target == "-" {
+        return Ok(None);
+    }
+    match target.parse() {
+        Ok(fd) => Ok(Some(fd)),
+        Err(_) => Err(format!("{}: ambiguous redirect", target))
+    }
+}
+
+fn open_file(kind: RedirectKind, target: &str) -> Result<::std::fs::File, String> {
+    use std::fs::OpenOptions;
+
+    let mut options = OpenOptions::new();
+    match kind {
+        RedirectKind::From => options.read(true),
+        RedirectKind::Insert => options.write(true).create(true).truncate(true),
+        RedirectKind::Append => options.append(true).create(true),
+        RedirectKind::Dup => unreachable!()
+    };
+    options.open(target).map_err(|e| format!("Cannot create/open the file {}: {}", target, e))
+}
+
+/// Output written by builtins is buffered, so it has to be flushed before the descriptors change
+fn flush_std() {
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::io;
+    use std::os::unix::io::IntoRawFd;
+    use libc;
+
+    use super::Source;
+    use runner::command::RunConfig;
+
+    /// Copies `fd` above the standard descriptors so it can be restored later
+    pub fn save(fd: i32) -> Option<i32> {
+        let saved = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) };
+        if saved < 0 {
+            None
+        } else {
+            Some(saved)
+        }
+    }
+
+    pub fn restore(fd: i32, saved: Option<i32>) {
+        unsafe {
+            match saved {
+                Some(saved) => {
+                    libc::dup2(saved, fd);
+                    libc::close(saved);
+                }
+                None => {
+                    libc::close(fd);
+                }
+            }
+        }
+    }
+
+    pub fn discard(saved: Option<i32>) {
+        if let Some(saved) = saved {
+            unsafe { libc::close(saved) };
+        }
+    }
+
+    pub fn replace(fd: i32, source: Source, _conf: &mut RunConfig) -> io::Result<()> {
+        match source {
+            Source::File(file) => {
+                let new_fd = file.into_raw_fd();
+                if new_fd != fd {
+                    let res = unsafe { libc::dup2(new_fd, fd) };
+                    unsafe { libc::close(new_fd) };
+                    if res < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            }
+            Source::Fd(Some(source)) => {
+                if source != fd && unsafe { libc::dup2(source, fd) } < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            }
+            Source::Fd(None) => {
+                unsafe { libc::close(fd) };
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::io;
+    use std::process::Stdio;
+
+    use super::Source;
+    use runner::command::RunConfig;
+
+    pub fn save(_fd: i32) -> Option<i32> {
+        None
+    }
+
+    pub fn restore(_fd: i32, _saved: Option<i32>) {}
+
+    pub fn discard(_saved: Option<i32>) {}
+
+    /// Only files for the standard input and output can be given to the process, builtins are not redirected
+    pub fn replace(fd: i32, source: Source, conf: &mut RunConfig) -> io::Result<()> {
+        match (fd, source) {
+            (0, Source::File(file)) => conf.input = Some(Stdio::from(file)),
+            (1, Source::File(file)) => conf.output = Some(Stdio::from(file)),
+            _ => return Err(io::Error::new(io::ErrorKind::Other, "This redirection is not supported on this platform"))
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,412 @@
+/*
+ * Project: rusth
+ * File: runner/shell.rs
+ * Copyright 2018 Caner Korkmaz (Kausta) <info@canerkorkmaz.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::env;
+use std::mem;
+use std::path::PathBuf;
+use std::process;
+
+use parser;
+use super::arith;
+#[cfg(unix)]
+use super::job;
+use super::command::{Runnable, RunnableCmd, RunConfig};
+use super::dirs;
+use super::procsub::ProcessSub;
+use super::vars::{Value, Variables};
+
+/// Control flow requested by a builtin, unwinds the running commands until it is handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    /// `break N`
+    Break(u32),
+    /// `continue N`
+    Continue(u32),
+    /// `return N`, with the exit code of the function
+    Return(Option<i32>),
+    /// The shell exits with the code, like after a failing command with `errexit`
+    Exit(Option<i32>),
+}
+
+/// Options that change how the shell runs commands, set with `-o name`
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Exit when a command fails outside of a condition, `-e`
+    pub errexit: bool,
+    /// Print the commands before running them, `-x`
+    pub xtrace: bool,
+    /// Change into a directory given as a command name that is not found
+    pub autocd: bool,
+}
+
+impl Options {
+    pub fn set(&mut self, name: &str, on: bool) -> Result<(), String> {
+        match name {
+            "errexit" => self.errexit = on,
+            "xtrace" => self.xtrace = on,
+            "autocd" => self.autocd = on,
+            _ => return Err(format!("{}: invalid option name", name))
+        }
+        Ok(())
+    }
+
+    /// Returns the options with their names, as listed by `set -o`
+    pub fn list(&self) -> Vec<(&'static str, bool)> {
+        vec![("autocd", self.autocd), ("errexit", self.errexit), ("xtrace", self.xtrace)]
+    }
+}
+
+/// Array subscript after it is evaluated
+enum Subscript {
+    Key(String),
+    Index(i64),
+}
+
+/// State of the running shell, shared by every command it runs
+#[derive(Clone)]
+pub struct Shell {
+    pub vars: Variables,
+    pub control: Option<Control>,
+    /// Number of loops currently running, limits `break` and `continue`
+    pub loop_depth: u32,
+    /// Number of functions currently running, limits `return` and `local`
+    pub function_depth: u32,
+    /// Number of files being run by `source`, which can also be left with `return`
+    pub source_depth: u32,
+    /// Number of conditions currently running, like the one of an `if`, where `errexit` is ignored
+    pub condition_depth: u32,
+    pub options: Options,
+    pub functions: HashMap<String, Rc<Runnable>>,
+    /// Positional parameters starting from `$1`
+    pub positional: Vec<String>,
+    /// Exit code of the last command
+    pub last_status: Option<i32>,
+    /// Commands set by `trap`, keyed by the condition name
+    pub traps: HashMap<String, String>,
+    /// Process substitutions of the commands that are running
+    pub process_subs: Vec<ProcessSub>,
+    /// Name of the shell or of the running script, `$0`
+    pub name: String,
+    /// Process id of the shell, `$$`, which subshells do not change
+    pub pid: u32,
+    /// Last argument of the previous command, `$_`
+    pub last_arg: String,
+    /// Process id of the last command started with `&`, `$!`
+    pub last_background: Option<i32>,
+    /// Processes started with `&` that have not finished yet
+    pub background: Vec<i32>,
+    /// Commands are read from the user with the line editor
+    pub interactive: bool,
+    pub login: bool,
+    /// `exit` already warned about running jobs, so the next one exits anyway
+    pub jobs_warned: bool,
+    /// Directories saved by `pushd` below the current one, the last one pushed first
+    pub dir_stack: Vec<PathBuf>,
+}
+
+impl Shell {
+    pub fn new() -> Shell {
+        let mut vars = Variables::from_env();
+        dirs::init(&mut vars);
+        Shell {
+            vars,
+            control: None,
+            loop_depth: 0,
+            function_depth: 0,
+            source_depth: 0,
+            condition_depth: 0,
+            options: Options::default(),
+            functions: HashMap::new(),
+            positional: Vec::new(),
+            last_status: Some(0),
+            traps: HashMap::new(),
+            process_subs: Vec::new(),
+            name: env::args().next().unwrap_or_else(|| "rusth".to_string()),
+            pid: process::id(),
+            last_arg: String::new(),
+            last_background: None,
+            background: Vec::new(),
+            interactive: false,
+            login: false,
+            jobs_warned: false,
+            dir_stack: Vec::new(),
+        }
+    }
+
+    /// Assigns a variable, the value is evaluated first if the variable has the integer attribute
+    pub fn assign(&mut self, name: &str, value: String) -> Result<(), String> {
+        let value = self.integer_value(name, value)?;
+        self.vars.set(name, value)
+    }
+
+    /// Assigns an element of an array, `subscript` is a key for associative arrays
+    /// and an arithmetic expression for indexed ones
+    pub fn assign_element(&mut self, name: &str, subscript: &str, value: String) -> Result<(), String> {
+        let value = self.integer_value(name, value)?;
+        match self.subscript(name, subscript)? {
+            Subscript::Key(key) => self.vars.set_key(name, key, value),
+            Subscript::Index(index) => self.vars.set_index(name, index, value)
+        }
+    }
+
+    /// Replaces the variable with an array, elements with a subscript are put at it
+    /// and the others after the previous element
+    pub fn assign_array(&mut self, name: &str, elements: Vec<(Option<String>, String)>) -> Result<(), String> {
+        if let Some(&Value::Assoc(_)) = self.vars.get_var(name).and_then(|var| var.value.as_ref()) {
+            let mut map = BTreeMap::new();
+            for (key, value) in elements {
+                match key {
+                    Some(key) => map.insert(key, self.integer_value(name, value)?),
+                    None => return Err(format!("{}: {}: must use subscript when assigning associative array", name, value))
+                };
+            }
+            return self.vars.set_value(name, Value::Assoc(map));
+        }
+        let mut map = BTreeMap::new();
+        let mut next = 0;
+        for (key, value) in elements {
+            let index = match key {
+                Some(key) => arith::eval(self, &key)?,
+                None => next
+            };
+            if index < 0 {
+                return Err(format!("{}[{}]: bad array subscript", name, index));
+            }
+            map.insert(index, self.integer_value(name, value)?);
+            next = index + 1;
+        }
+        self.vars.set_value(name, Value::Indexed(map))
+    }
+
+    /// Returns an element of an array, None if it is not set
+    pub fn element(&mut self, name: &str, subscript: &str) -> Result<Option<String>, String> {
+        let subscript = self.subscript(name, subscript)?;
+        let value = match self.vars.get_var(name).and_then(|var| var.value.as_ref()) {
+            Some(value) => value,
+            None => return Ok(None)
+        };
+        Ok(match (subscript, value) {
+            (Subscript::Key(ref key), &Value::Assoc(ref elements)) => elements.get(key).cloned(),
+            (Subscript::Index(index), &Value::Indexed(ref elements)) => elements.get(&index).cloned(),
+            (Subscript::Index(0), &Value::Scalar(ref value)) => Some(value.clone()),
+            _ => None
+        })
+    }
+
+    /// Removes an element of an array
+    pub fn unset_element(&mut self, name: &str, subscript: &str) -> Result<(), String> {
+        let key = match self.subscript(name, subscript)? {
+            Subscript::Key(key) => key,
+            Subscript::Index(index) => index.to_string()
+        };
+        self.vars.unset_element(name, &key)
+    }
+
+    /// Resolves a subscript of the variable, negative indexes count from the end of the array
+    fn subscript(&mut self, name: &str, subscript: &str) -> Result<Subscript, String> {
+        let len = match self.vars.get_var(name).and_then(|var| var.value.as_ref()) {
+            Some(&Value::Assoc(_)) => return Ok(Subscript::Key(subscript.to_string())),
+            Some(&Value::Indexed(ref elements)) => elements.keys().next_back().map_or(0, |last| last + 1),
+            Some(&Value::Scalar(_)) => 1,
+            None => 0
+        };
+        let index = arith::eval(self, subscript)?;
+        if index >= 0 {
+            Ok(Subscript::Index(index))
+        } else if len + index >= 0 {
+            Ok(Subscript::Index(len + index))
+        } else {
+            Err(format!("{}[{}]: bad array subscript", name, subscript))
+        }
+    }
+
+    fn integer_value(&mut self, name: &str, value: String) -> Result<String, String> {
+        if self.vars.get_var(name).map_or(false, |var| var.integer) {
+            Ok(arith::eval(self, &value)?.to_string())
+        } else {
+            Ok(value)
+        }
+    }
+
+    /// Runs a command as a subshell, which starts without the traps of its parent
+    /// and runs its own exit trap at the end
+    pub fn run_in_child(&mut self, cmd: &Runnable) -> Option<i32> {
+        self.traps.clear();
+        self.control = None;
+        self.loop_depth = 0;
+        // Jobs of the parent are not the ones of the subshell
+        self.background.clear();
+        self.interactive = false;
+        let mut code = cmd.run(self, RunConfig::default());
+        if let Some(Control::Exit(exit_code)) = self.control {
+            code = exit_code;
+        }
+        self.run_exit_trap();
+        code
+    }
+
+    /// Runs a command whose status is tested, so that it does not trigger `errexit`
+    pub fn run_condition(&mut self, cmd: &Runnable) -> Option<i32> {
+        self.condition_depth += 1;
+        let code = cmd.run(self, RunConfig::default());
+        self.condition_depth -= 1;
+        code
+    }
+
+    /// Requests the shell to exit if the command failed and `errexit` is set
+    pub fn check_errexit(&mut self, code: Option<i32>) {
+        if code != Some(0) && self.options.errexit && self.condition_depth == 0 && self.control.is_none() {
+            self.control = Some(Control::Exit(code));
+        }
+    }
+
+    /// Returns the exit code if the shell should exit
+    pub fn exit_requested(&self) -> Option<Option<i32>> {
+        match self.control {
+            Some(Control::Exit(code)) => Some(code),
+            _ => None
+        }
+    }
+
+    /// Runs the command set by `trap ... EXIT`, if there is one
+    pub fn run_exit_trap(&mut self) {
+        if let Some(trap) = self.traps.remove("EXIT") {
+            match parser::parse(&trap) {
+                Ok(cmd) => {
+                    self.control = None;
+                    let status = self.last_status;
+                    cmd.run(self, RunConfig::default());
+                    self.last_status = status;
+                }
+                Err(e) => eprintln!("trap: {}", e)
+            }
+        }
+    }
+
+    /// Runs a function body with `args` as the positional parameters and its own local variables
+    pub fn call_function(&mut self, body: &Runnable, args: &[String]) -> Option<i32> {
+        let positional = mem::replace(&mut self.positional, args.to_vec());
+        // Loops of the caller cannot be controlled from the function
+        let loop_depth = mem::replace(&mut self.loop_depth, 0);
+        self.function_depth += 1;
+        self.vars.push_scope();
+
+        let mut code = body.run(self, RunConfig::default());
+        if let Some(Control::Return(ret)) = self.control {
+            self.control = None;
+            code = ret;
+        }
+
+        self.vars.pop_scope();
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+        self.positional = positional;
+        code
+    }
+
+    /// Forgets the background processes that have finished
+    #[cfg(unix)]
+    pub fn reap_background(&mut self) {
+        self.background.retain(|&pid| !job::finished(pid));
+    }
+
+    #[cfg(not(unix))]
+    pub fn reap_background(&mut self) {}
+
+    /// Returns true if the remaining commands should be skipped because of pending control flow
+    pub fn is_interrupted(&self) -> bool {
+        self.control.is_some()
+    }
+
+    /// Handles the pending control flow at the end of a loop iteration,
+    /// returns true if the loop should stop
+    pub fn end_iteration(&mut self) -> bool {
+        match self.control {
+            Some(Control::Break(n)) => {
+                self.control = if n > 1 { Some(Control::Break(n - 1)) } else { None };
+                true
+            }
+            Some(Control::Continue(n)) => {
+                if n > 1 {
+                    self.control = Some(Control::Continue(n - 1));
+                    true
+                } else {
+                    self.control = None;
+                    false
+                }
+            }
+            Some(Control::Return(_)) | Some(Control::Exit(_)) => true,
+            None => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<(Option<String>, String)> {
+        values.iter().map(|value| (None, value.to_string())).collect()
+    }
+
+    #[test]
+    fn indexed_subscripts() {
+        let mut shell = Shell::new();
+        let mut elements = strings(&["a", "b"]);
+        elements.push((Some("2 * 5".to_string()), "c".to_string()));
+        elements.extend(strings(&["d"]));
+        shell.assign_array("arr", elements).unwrap();
+        assert_eq!(shell.vars.get_var("arr").unwrap().value.as_ref().unwrap().keys(), vec!["0", "1", "10", "11"]);
+        // Subscripts are arithmetic, negative ones count from the end
+        assert_eq!(shell.element("arr", "5 + 5").unwrap(), Some("c".to_string()));
+        assert_eq!(shell.element("arr", "-1").unwrap(), Some("d".to_string()));
+        assert_eq!(shell.element("arr", "-2").unwrap(), Some("c".to_string()));
+        assert_eq!(shell.element("arr", "5").unwrap(), None);
+        assert!(shell.element("arr", "-13").is_err());
+        shell.assign_element("arr", "-1", "e".to_string()).unwrap();
+        assert_eq!(shell.element("arr", "11").unwrap(), Some("e".to_string()));
+        shell.unset_element("arr", "-1").unwrap();
+        assert_eq!(shell.element("arr", "-1").unwrap(), Some("c".to_string()));
+        assert!(shell.assign_array("arr", vec![(Some("-1".to_string()), "x".to_string())]).is_err());
+    }
+
+    #[test]
+    fn scalar_subscripts() {
+        let mut shell = Shell::new();
+        shell.assign("s", "value".to_string()).unwrap();
+        assert_eq!(shell.element("s", "0").unwrap(), Some("value".to_string()));
+        assert_eq!(shell.element("s", "-1").unwrap(), Some("value".to_string()));
+        assert_eq!(shell.element("s", "1").unwrap(), None);
+        assert!(shell.element("unset_name", "-1").is_err());
+    }
+
+    #[test]
+    fn associative_subscripts() {
+        let mut shell = Shell::new();
+        shell.vars.set_value("m", Value::Assoc(BTreeMap::new())).unwrap();
+        // Keys are not evaluated
+        shell.assign_element("m", "1 + 1", "a".to_string()).unwrap();
+        assert_eq!(shell.element("m", "1 + 1").unwrap(), Some("a".to_string()));
+        assert_eq!(shell.element("m", "2").unwrap(), None);
+        assert!(shell.assign_array("m", strings(&["x"])).is_err());
+        shell.assign_array("m", vec![(Some("k".to_string()), "v".to_string())]).unwrap();
+        assert_eq!(shell.vars.get_var("m").unwrap().value.as_ref().unwrap().keys(), vec!["k"]);
+    }
+}
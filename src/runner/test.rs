@@ -0,0 +1,597 @@
+/*
+ * Project: rusth
+ * File: runner/test.rs
+ * Copyright 2018 Caner Korkmaz (Kausta) <info@canerkorkmaz.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::fs;
+use std::path::Path;
+
+use super::arith;
+use super::command::Command;
+use super::expand::{expand_word, expand_pattern, expand_regex};
+use super::shell::Shell;
+use util::glob;
+
+/// `test` and `[`, evaluates the arguments as a conditional expression,
+/// the status is 0 if it is true, 1 if it is false and 2 if it is malformed
+pub fn test(shell: &mut Shell, cmd: &Command) -> Option<i32> {
+    let name = cmd.args[0].as_str();
+    let mut args: Vec<&str> = cmd.args[1..].iter().map(|arg| arg.as_str()).collect();
+    if name == "[" {
+        if args.last() != Some(&"]") {
+            eprintln!("[: missing `]'");
+            return Some(2);
+        }
+        args.pop();
+    }
+    match posix_test(shell, &args) {
+        Ok(true) => Some(0),
+        Ok(false) => Some(1),
+        Err(e) => {
+            eprintln!("{}: {}", name, e);
+            Some(2)
+        }
+    }
+}
+
+/// Evaluates up to four arguments by their count like POSIX specifies, so that operands looking
+/// like operators are not misread, longer expressions are parsed with `-a`, `-o`, `!` and parentheses
+fn posix_test(shell: &mut Shell, args: &[&str]) -> Result<bool, String> {
+    match args.len() {
+        0 => Ok(false),
+        1 => Ok(!args[0].is_empty()),
+        2 if args[0] == "!" => Ok(args[1].is_empty()),
+        2 if is_unary(args[0]) => Ok(unary(shell, args[0], args[1])),
+        2 => Err(format!("{}: unary operator expected", args[0])),
+        3 if is_binary(args[1]) => binary(args[0], args[1], args[2]),
+        3 if args[1] == "-a" => Ok(!args[0].is_empty() && !args[2].is_empty()),
+        3 if args[1] == "-o" => Ok(!args[0].is_empty() || !args[2].is_empty()),
+        3 if args[0] == "!" => posix_test(shell, &args[1..]).map(|res| !res),
+        3 if args[0] == "(" && args[2] == ")" => Ok(!args[1].is_empty()),
+        3 => Err(format!("{}: binary operator expected", args[1])),
+        4 if args[0] == "!" => posix_test(shell, &args[1..]).map(|res| !res),
+        4 if args[0] == "(" && args[3] == ")" => posix_test(shell, &args[1..3]),
+        _ => {
+            let mut parser = TestParser { shell, args, pos: 0 };
+            let res = parser.or()?;
+            if parser.pos < args.len() {
+                return Err("too many arguments".to_string());
+            }
+            Ok(res)
+        }
+    }
+}
+
+/// Parses the arguments of `test` with `-o` binding looser than `-a`
+struct TestParser<'s, 'a> {
+    shell: &'s mut Shell,
+    args: &'a [&'a str],
+    pos: usize,
+}
+
+impl<'s, 'a> TestParser<'s, 'a> {
+    fn peek(&self, offset: usize) -> Option<&'a str> {
+        self.args.get(self.pos + offset).cloned()
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut res = self.and()?;
+        while self.peek(0) == Some("-o") {
+            self.pos += 1;
+            res = self.and()? || res;
+        }
+        Ok(res)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut res = self.term()?;
+        while self.peek(0) == Some("-a") {
+            self.pos += 1;
+            res = self.term()? && res;
+        }
+        Ok(res)
+    }
+
+    fn term(&mut self) -> Result<bool, String> {
+        let first = self.peek(0).ok_or_else(|| "argument expected".to_string())?;
+        if let Some(op) = self.peek(1) {
+            if is_binary(op) {
+                let right = self.peek(2).ok_or_else(|| format!("{}: argument expected", op))?;
+                self.pos += 3;
+                return binary(first, op, right);
+            }
+        }
+        self.pos += 1;
+        match first {
+            "!" => self.term().map(|res| !res),
+            "(" => {
+                let res = self.or()?;
+                if self.peek(0) != Some(")") {
+                    return Err("`)' expected".to_string());
+                }
+                self.pos += 1;
+                Ok(res)
+            }
+            op if is_unary(op) => match self.peek(0) {
+                Some(operand) => {
+                    self.pos += 1;
+                    Ok(unary(self.shell, op, operand))
+                }
+                None => Err(format!("{}: argument expected", op))
+            },
+            operand => Ok(!operand.is_empty())
+        }
+    }
+}
+
+/// Evaluates the words of `[[ ... ]]`, the operands are expanded without word splitting
+/// or pathname expansion, and only when they are needed for the result
+pub fn eval_cond(shell: &mut Shell, words: &[String]) -> Result<bool, String> {
+    let mut parser = CondParser { shell, words, pos: 0 };
+    let res = parser.or(true)?;
+    match parser.peek(0) {
+        Some(word) => Err(format!("syntax error near `{}'", word)),
+        None => Ok(res)
+    }
+}
+
+/// Parses the words of `[[ ... ]]`, an expression is evaluated only if `eval` is set,
+/// so `&&` and `||` skip the expansions on the side that does not matter
+struct CondParser<'s, 'w> {
+    shell: &'s mut Shell,
+    words: &'w [String],
+    pos: usize,
+}
+
+impl<'s, 'w> CondParser<'s, 'w> {
+    fn peek(&self, offset: usize) -> Option<&'w str> {
+        self.words.get(self.pos + offset).map(|word| word.as_str())
+    }
+
+    fn or(&mut self, eval: bool) -> Result<bool, String> {
+        let mut res = self.and(eval)?;
+        while self.peek(0) == Some("||") {
+            self.pos += 1;
+            res = self.and(eval && !res)? || res;
+        }
+        Ok(res)
+    }
+
+    fn and(&mut self, eval: bool) -> Result<bool, String> {
+        let mut res = self.not(eval)?;
+        while self.peek(0) == Some("&&") {
+            self.pos += 1;
+            res = self.not(eval && res)? && res;
+        }
+        Ok(res)
+    }
+
+    fn not(&mut self, eval: bool) -> Result<bool, String> {
+        match self.peek(0) {
+            Some("!") => {
+                self.pos += 1;
+                self.not(eval).map(|res| eval && !res)
+            }
+            Some("(") => {
+                self.pos += 1;
+                let res = self.or(eval)?;
+                if self.peek(0) != Some(")") {
+                    return Err("expected `)'".to_string());
+                }
+                self.pos += 1;
+                Ok(res)
+            }
+            _ => self.primary(eval)
+        }
+    }
+
+    fn primary(&mut self, eval: bool) -> Result<bool, String> {
+        let first = match self.peek(0) {
+            Some(word) if !is_cond_operator(word) => word,
+            Some(word) => return Err(format!("syntax error near `{}'", word)),
+            None => return Err("unexpected end of expression".to_string())
+        };
+        if let Some(op) = self.peek(1) {
+            if op == "=~" || is_binary(op) {
+                let right = match self.peek(2) {
+                    Some(word) if !is_cond_operator(word) => word,
+                    _ => return Err(format!("unexpected argument to conditional binary operator `{}'", op))
+                };
+                self.pos += 3;
+                return if eval { self.binary(first, op, right) } else { Ok(false) };
+            }
+        }
+        if is_unary(first) {
+            if let Some(operand) = self.peek(1).filter(|word| !is_cond_operator(word)) {
+                self.pos += 2;
+                if !eval {
+                    return Ok(false);
+                }
+                let operand = expand_word(self.shell, operand);
+                return Ok(unary(self.shell, first, &operand));
+            }
+        }
+        self.pos += 1;
+        Ok(eval && !expand_word(self.shell, first).is_empty())
+    }
+
+    /// `==` and `!=` match the right side as a glob pattern, `=~` as an extended regular expression
+    /// setting `BASH_REMATCH`, and the integer operators evaluate both sides as arithmetic expressions
+    fn binary(&mut self, left: &str, op: &str, right: &str) -> Result<bool, String> {
+        let left = expand_word(self.shell, left);
+        match op {
+            "=" | "==" | "!=" => {
+                let pattern = expand_pattern(self.shell, right);
+                Ok(glob::matches(&pattern, &left) == (op != "!="))
+            }
+            "=~" => {
+                let pattern = expand_regex(self.shell, right);
+                let groups = match sys::regex_match(&pattern, &left)? {
+                    Some(groups) => groups,
+                    None => {
+                        self.shell.assign_array("BASH_REMATCH", Vec::new())?;
+                        return Ok(false);
+                    }
+                };
+                let elements = groups.into_iter().map(|group| (None, group)).collect();
+                self.shell.assign_array("BASH_REMATCH", elements)?;
+                Ok(true)
+            }
+            "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+                let right = expand_word(self.shell, right);
+                let a = arith::eval(self.shell, &left)?;
+                let b = arith::eval(self.shell, &right)?;
+                Ok(compare_integers(op, a, b))
+            }
+            _ => {
+                let right = expand_word(self.shell, right);
+                binary(&left, op, &right)
+            }
+        }
+    }
+}
+
+fn is_cond_operator(word: &str) -> bool {
+    match word {
+        "&&" | "||" | "(" | ")" => true,
+        _ => false
+    }
+}
+
+fn is_unary(op: &str) -> bool {
+    match op {
+        "-e" | "-a" | "-f" | "-d" | "-r" | "-w" | "-x" | "-s" | "-L" | "-h" | "-b" | "-c" | "-p" | "-S" |
+        "-g" | "-u" | "-k" | "-O" | "-G" | "-N" | "-t" | "-z" | "-n" | "-v" => true,
+        _ => false
+    }
+}
+
+/// Evaluates a unary operator, the file operators are false for files that do not exist
+fn unary(shell: &mut Shell, op: &str, operand: &str) -> bool {
+    let path = Path::new(operand);
+    match op {
+        "-z" => operand.is_empty(),
+        "-n" => !operand.is_empty(),
+        "-v" => is_set(shell, operand),
+        "-t" => operand.trim().parse().map(sys::is_tty).unwrap_or(false),
+        "-e" | "-a" => path.exists(),
+        "-f" => path.is_file(),
+        "-d" => path.is_dir(),
+        "-s" => fs::metadata(path).map(|meta| meta.len() > 0).unwrap_or(false),
+        "-L" | "-h" => fs::symlink_metadata(path).map(|meta| meta.file_type().is_symlink()).unwrap_or(false),
+        _ => sys::file_test(op, path)
+    }
+}
+
+/// Returns true if the variable, or the element for `name[subscript]`, has a value
+fn is_set(shell: &mut Shell, name: &str) -> bool {
+    if name.ends_with(']') {
+        if let Some(idx) = name.find('[') {
+            let subscript = &name[idx + 1..name.len() - 1];
+            return shell.element(&name[..idx], subscript).ok().map_or(false, |value| value.is_some());
+        }
+    }
+    shell.vars.get_var(name).map_or(false, |var| var.value.is_some())
+}
+
+fn is_binary(op: &str) -> bool {
+    match op {
+        "=" | "==" | "!=" | "<" | ">" | "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" | "-nt" | "-ot" | "-ef" => true,
+        _ => false
+    }
+}
+
+/// Evaluates a binary operator on strings, integers or files
+fn binary(left: &str, op: &str, right: &str) -> Result<bool, String> {
+    match op {
+        "=" | "==" => Ok(left == right),
+        "!=" => Ok(left != right),
+        "<" => Ok(left < right),
+        ">" => Ok(left > right),
+        "-nt" => Ok(is_newer(left, right)),
+        "-ot" => Ok(is_newer(right, left)),
+        "-ef" => Ok(sys::same_file(Path::new(left), Path::new(right))),
+        _ => Ok(compare_integers(op, parse_integer(left)?, parse_integer(right)?))
+    }
+}
+
+fn compare_integers(op: &str, a: i64, b: i64) -> bool {
+    match op {
+        "-eq" => a == b,
+        "-ne" => a != b,
+        "-lt" => a < b,
+        "-le" => a <= b,
+        "-gt" => a > b,
+        _ => a >= b
+    }
+}
+
+fn parse_integer(arg: &str) -> Result<i64, String> {
+    arg.trim().parse().map_err(|_| format!("{}: integer expression expected", arg))
+}
+
+/// Returns true if the first file was modified after the second one, or if only the first one exists
+fn is_newer(a: &str, b: &str) -> bool {
+    let modified = |path: &str| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    match (modified(a), modified(b)) {
+        (Some(a), Some(b)) => a > b,
+        (Some(_), None) => true,
+        _ => false
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::CString;
+    use std::fs;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+    use std::path::Path;
+    use libc;
+
+    pub fn is_tty(fd: i32) -> bool {
+        unsafe { libc::isatty(fd) == 1 }
+    }
+
+    /// Evaluates the file operators that need the permissions, the type or the owner of the file
+    pub fn file_test(op: &str, path: &Path) -> bool {
+        let access = |mode| match CString::new(path.as_os_str().as_bytes()) {
+            Ok(path) => unsafe { libc::access(path.as_ptr(), mode) == 0 },
+            Err(_) => false
+        };
+        match op {
+            "-r" => return access(libc::R_OK),
+            "-w" => return access(libc::W_OK),
+            "-x" => return access(libc::X_OK),
+            _ => {}
+        }
+        let meta = match fs::metadata(path) {
+            Ok(meta) => meta,
+            Err(_) => return false
+        };
+        match op {
+            "-b" => meta.file_type().is_block_device(),
+            "-c" => meta.file_type().is_char_device(),
+            "-p" => meta.file_type().is_fifo(),
+            "-S" => meta.file_type().is_socket(),
+            "-u" => meta.mode() & 0o4000 != 0,
+            "-g" => meta.mode() & 0o2000 != 0,
+            "-k" => meta.mode() & 0o1000 != 0,
+            "-O" => meta.uid() == unsafe { libc::geteuid() },
+            "-G" => meta.gid() == unsafe { libc::getegid() },
+            "-N" => meta.mtime() > meta.atime() || (meta.mtime() == meta.atime() && meta.mtime_nsec() > meta.atime_nsec()),
+            _ => false
+        }
+    }
+
+    pub fn same_file(a: &Path, b: &Path) -> bool {
+        match (fs::metadata(a), fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false
+        }
+    }
+
+    /// Matches the text against an extended regular expression, returns the whole match
+    /// followed by the text matched by each group, which is empty for groups that did not take part.
+    /// The offsets are in bytes and may split a character in the C locale, such a match is lossy.
+    pub fn regex_match(pattern: &str, text: &str) -> Result<Option<Vec<String>>, String> {
+        let invalid = || format!("{}: invalid regular expression", pattern);
+        let c_pattern = CString::new(pattern).map_err(|_| invalid())?;
+        let c_text = CString::new(text).map_err(|_| invalid())?;
+        let mut matches = vec![libc::regmatch_t { rm_so: -1, rm_eo: -1 }; count_groups(pattern) + 1];
+        unsafe {
+            let mut regex: libc::regex_t = ::std::mem::zeroed();
+            if libc::regcomp(&mut regex, c_pattern.as_ptr(), libc::REG_EXTENDED) != 0 {
+                return Err(invalid());
+            }
+            let res = libc::regexec(&regex, c_text.as_ptr(), matches.len(), matches.as_mut_ptr(), 0);
+            libc::regfree(&mut regex);
+            if res != 0 {
+                return Ok(None);
+            }
+        }
+        let groups = matches.iter()
+            .map(|m| {
+                if m.rm_so < 0 {
+                    String::new()
+                } else {
+                    String::from_utf8_lossy(&text.as_bytes()[m.rm_so as usize..m.rm_eo as usize]).into_owned()
+                }
+            })
+            .collect();
+        Ok(Some(groups))
+    }
+
+    /// Counts the groups in the regular expression, the parentheses that are not escaped
+    /// or in a bracket expression
+    fn count_groups(pattern: &str) -> usize {
+        let mut count = 0;
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                '[' => {
+                    // A `]` right after the opening bracket is part of the expression
+                    chars.next_if_eq(&'^');
+                    chars.next_if_eq(&']');
+                    while let Some(c) = chars.next() {
+                        match c {
+                            ']' => break,
+                            '[' if chars.peek().map_or(false, |&c| c == ':' || c == '.' || c == '=') => {
+                                let delimiter = chars.next().unwrap_or(':');
+                                while let Some(c) = chars.next() {
+                                    if c == delimiter && chars.next_if_eq(&']').is_some() {
+                                        break;
+                                    }
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                '(' => count += 1,
+                _ => {}
+            }
+        }
+        count
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::fs;
+    use std::path::Path;
+
+    pub fn is_tty(_fd: i32) -> bool {
+        false
+    }
+
+    /// Only the permissions can be checked here, from the readonly flag
+    pub fn file_test(op: &str, path: &Path) -> bool {
+        match fs::metadata(path) {
+            Ok(meta) => match op {
+                "-r" | "-x" => true,
+                "-w" => !meta.permissions().readonly(),
+                _ => false
+            },
+            Err(_) => false
+        }
+    }
+
+    pub fn same_file(a: &Path, b: &Path) -> bool {
+        match (fs::canonicalize(a), fs::canonicalize(b)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false
+        }
+    }
+
+    pub fn regex_match(_pattern: &str, _text: &str) -> Result<Option<Vec<String>>, String> {
+        Err("=~: regular expressions are not supported on this platform".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_args(args: &[&str]) -> Result<bool, String> {
+        posix_test(&mut Shell::new(), args)
+    }
+
+    fn cond(shell: &mut Shell, expr: &str) -> Result<bool, String> {
+        let words = expr.split_whitespace().map(|word| word.to_string()).collect::<Vec<_>>();
+        eval_cond(shell, &words)
+    }
+
+    #[test]
+    fn argument_count() {
+        assert_eq!(test_args(&[]), Ok(false));
+        assert_eq!(test_args(&["-n"]), Ok(true));
+        assert_eq!(test_args(&[""]), Ok(false));
+        assert_eq!(test_args(&["!", ""]), Ok(true));
+        assert_eq!(test_args(&["-z", ""]), Ok(true));
+        assert_eq!(test_args(&["=", "=", "="]), Ok(true));
+        assert_eq!(test_args(&["!", "=", "x"]), Ok(false));
+        assert_eq!(test_args(&["(", "", ")"]), Ok(false));
+        assert_eq!(test_args(&["!", "a", "=", "a"]), Ok(false));
+        assert_eq!(test_args(&["(", "-n", "", ")"]), Ok(false));
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(test_args(&["a", "-o", "b", "-a", ""]), Ok(true));
+        assert_eq!(test_args(&["", "-a", "b", "-o", "c"]), Ok(true));
+        assert_eq!(test_args(&["(", "a", "-o", "b", ")", "-a", ""]), Ok(false));
+        assert_eq!(test_args(&["!", "", "-a", "a", "-o", ""]), Ok(true));
+        assert_eq!(test_args(&["1", "-lt", "2", "-a", "-z", "", "-a", "b", "!=", "c"]), Ok(true));
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(test_args(&["a", "b"]).is_err());
+        assert!(test_args(&["a", "b", "c"]).is_err());
+        assert!(test_args(&["1", "-eq", "x"]).is_err());
+        assert!(test_args(&["(", "a", "-a", "b"]).is_err());
+        assert!(test_args(&["a", "-a", "b", "c", "d"]).is_err());
+        assert!(test_args(&["a", "-a", "b", "-o"]).is_err());
+        assert!(cond(&mut Shell::new(), "a &&").is_err());
+        assert!(cond(&mut Shell::new(), "( a").is_err());
+        assert!(cond(&mut Shell::new(), "a b").is_err());
+        assert!(cond(&mut Shell::new(), "a == && b").is_err());
+        assert!(cond(&mut Shell::new(), "a -eq 1 )").is_err());
+    }
+
+    #[test]
+    fn cond_expressions() {
+        let mut shell = Shell::new();
+        assert_eq!(cond(&mut shell, "a && ! -z b || ( -n '' )"), Ok(true));
+        assert_eq!(cond(&mut shell, "-n a && ( '' || '' )"), Ok(false));
+        assert_eq!(cond(&mut shell, "abc == a* && abc != *d"), Ok(true));
+        assert_eq!(cond(&mut shell, "abc == 'a*'"), Ok(false));
+        assert_eq!(cond(&mut shell, "1+1 -eq 2 && b < c"), Ok(true));
+    }
+
+    #[test]
+    fn short_circuit() {
+        let mut shell = Shell::new();
+        assert_eq!(cond(&mut shell, "'' && $((x=1))"), Ok(false));
+        assert_eq!(cond(&mut shell, "a || x=2 -eq 2"), Ok(true));
+        assert_eq!(cond(&mut shell, "'' && -n $((x=3)) || a"), Ok(true));
+        assert_eq!(cond(&mut shell, "a || abc =~ (b)"), Ok(true));
+        assert_eq!(shell.vars.get("x"), None);
+        assert_eq!(shell.vars.get("BASH_REMATCH"), None);
+        assert_eq!(cond(&mut shell, "a && x=4 -eq 4"), Ok(true));
+        assert_eq!(shell.vars.get("x"), Some("4"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn regex() {
+        let mut shell = Shell::new();
+        assert_eq!(cond(&mut shell, "abc =~ ^a(b|x)(y)?"), Ok(true));
+        assert_eq!(shell.element("BASH_REMATCH", "0"), Ok(Some("ab".to_string())));
+        assert_eq!(shell.element("BASH_REMATCH", "1"), Ok(Some("b".to_string())));
+        assert_eq!(shell.element("BASH_REMATCH", "2"), Ok(Some(String::new())));
+        assert_eq!(cond(&mut shell, "abc =~ ^x"), Ok(false));
+        assert_eq!(shell.element("BASH_REMATCH", "0"), Ok(None));
+        assert!(cond(&mut shell, "a =~ (").is_err());
+        // Offsets in the middle of a character must not panic
+        assert_eq!(cond(&mut shell, "é =~ ^."), Ok(true));
+        assert_eq!(cond(&mut shell, "aé =~ a(.)"), Ok(true));
+    }
+}
@@ -0,0 +1,429 @@
+/*
+ * Project: rusth
+ * File: runner/vars.rs
+ * Copyright 2018 Caner Korkmaz (Kausta) <info@canerkorkmaz.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Scalar(String),
+    /// Array with integer indexes, elements can be missing
+    Indexed(BTreeMap<i64, String>),
+    /// Array declared with `declare -A`, with string keys
+    Assoc(BTreeMap<String, String>),
+}
+
+impl Value {
+    /// Returns the value used when the variable is expanded without a subscript,
+    /// which is the element at `0` for arrays
+    pub fn scalar(&self) -> Option<&str> {
+        match *self {
+            Value::Scalar(ref value) => Some(value),
+            Value::Indexed(ref elements) => elements.get(&0).map(|value| value.as_str()),
+            Value::Assoc(ref elements) => elements.get("0").map(|value| value.as_str())
+        }
+    }
+
+    /// Returns the elements in order, a scalar is an array with a single element
+    pub fn values(&self) -> Vec<&str> {
+        match *self {
+            Value::Scalar(ref value) => vec![value],
+            Value::Indexed(ref elements) => elements.values().map(|value| value.as_str()).collect(),
+            Value::Assoc(ref elements) => elements.values().map(|value| value.as_str()).collect()
+        }
+    }
+
+    /// Returns the subscripts of the elements in order
+    pub fn keys(&self) -> Vec<String> {
+        match *self {
+            Value::Scalar(_) => vec!["0".to_string()],
+            Value::Indexed(ref elements) => elements.keys().map(|key| key.to_string()).collect(),
+            Value::Assoc(ref elements) => elements.keys().cloned().collect()
+        }
+    }
+}
+
+/// A shell variable with its attributes
+#[derive(Debug, Clone, Default)]
+pub struct Variable {
+    /// None if the variable is declared, e.g. by `export name`, but has no value yet
+    pub value: Option<Value>,
+    /// Passed to the environment of the processes the shell runs
+    pub exported: bool,
+    /// Cannot be assigned or unset
+    pub readonly: bool,
+    /// Values assigned to it are evaluated as arithmetic expressions
+    pub integer: bool,
+}
+
+#[derive(Clone)]
+pub struct Variables {
+    vars: HashMap<String, Variable>,
+    /// Variables replaced by `local` in each running function or by the assignments in front of a
+    /// running command, restored when it finishes
+    scopes: Vec<Vec<(String, Option<Variable>)>>,
+}
+
+impl Variables {
+    /// Creates an empty variable table
+    pub fn new() -> Variables {
+        Variables {
+            vars: HashMap::new(),
+            scopes: Vec::new(),
+        }
+    }
+
+    /// Creates the variables with the ones in the environment of the shell, all of them exported.
+    /// Names that are not valid unicode are skipped, such values are converted lossily.
+    pub fn from_env() -> Variables {
+        let mut vars = Variables::new();
+        for (name, value) in env::vars_os() {
+            let name = match name.into_string() {
+                Ok(name) => name,
+                Err(_) => continue
+            };
+            vars.vars.insert(name, Variable {
+                value: Some(Value::Scalar(value.to_string_lossy().into_owned())),
+                exported: true,
+                ..Variable::default()
+            });
+        }
+        vars
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).and_then(|var| var.value.as_ref()).and_then(|value| value.scalar())
+    }
+
+    /// Returns the variable with its attributes, even if it has no value
+    pub fn get_var(&self, name: &str) -> Option<&Variable> {
+        self.vars.get(name)
+    }
+
+    /// Assigns a value to the variable keeping its attributes, fails if it is readonly,
+    /// assigning to an array sets its element at `0`
+    pub fn set(&mut self, name: &str, value: String) -> Result<(), String> {
+        let var = self.writable(name)?;
+        match var.value {
+            Some(Value::Indexed(ref mut elements)) => {
+                elements.insert(0, value);
+            }
+            Some(Value::Assoc(ref mut elements)) => {
+                elements.insert("0".to_string(), value);
+            }
+            _ => var.value = Some(Value::Scalar(value))
+        }
+        Ok(())
+    }
+
+    /// Replaces the whole value of the variable, fails if it is readonly
+    pub fn set_value(&mut self, name: &str, value: Value) -> Result<(), String> {
+        self.writable(name)?.value = Some(value);
+        Ok(())
+    }
+
+    /// Sets an element of an indexed array, a scalar becomes the element at `0`
+    pub fn set_index(&mut self, name: &str, index: i64, value: String) -> Result<(), String> {
+        let var = self.writable(name)?;
+        let mut elements = match var.value.take() {
+            Some(Value::Indexed(elements)) => elements,
+            Some(Value::Scalar(old)) => vec![(0, old)].into_iter().collect(),
+            Some(Value::Assoc(elements)) => {
+                var.value = Some(Value::Assoc(elements));
+                return Err(format!("{}: not an indexed array", name));
+            }
+            None => BTreeMap::new()
+        };
+        elements.insert(index, value);
+        var.value = Some(Value::Indexed(elements));
+        Ok(())
+    }
+
+    /// Sets an element of an associative array
+    pub fn set_key(&mut self, name: &str, key: String, value: String) -> Result<(), String> {
+        match self.writable(name)?.value {
+            Some(Value::Assoc(ref mut elements)) => {
+                elements.insert(key, value);
+                Ok(())
+            }
+            _ => Err(format!("{}: not an associative array", name))
+        }
+    }
+
+    /// Removes an element of an array
+    pub fn unset_element(&mut self, name: &str, key: &str) -> Result<(), String> {
+        if !self.vars.contains_key(name) {
+            return Ok(());
+        }
+        let var = self.writable(name)?;
+        match var.value {
+            Some(Value::Indexed(ref mut elements)) => {
+                if let Ok(index) = key.parse() {
+                    elements.remove(&index);
+                }
+            }
+            Some(Value::Assoc(ref mut elements)) => {
+                elements.remove(key);
+            }
+            Some(Value::Scalar(_)) if key == "0" => var.value = None,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn writable(&mut self, name: &str) -> Result<&mut Variable, String> {
+        let var = self.vars.entry(name.to_string()).or_insert_with(Variable::default);
+        if var.readonly {
+            return Err(format!("{}: readonly variable", name));
+        }
+        Ok(var)
+    }
+
+    /// Removes the variable with its attributes, fails if it is readonly
+    pub fn unset(&mut self, name: &str) -> Result<(), String> {
+        if self.vars.get(name).map_or(false, |var| var.readonly) {
+            return Err(format!("{}: cannot unset: readonly variable", name));
+        }
+        self.vars.remove(name);
+        Ok(())
+    }
+
+    /// Returns the variable to change its attributes, declaring it without a value if needed
+    pub fn declare(&mut self, name: &str) -> &mut Variable {
+        self.vars.entry(name.to_string()).or_insert_with(Variable::default)
+    }
+
+    /// Returns the exported variables that have a value, arrays cannot be exported
+    pub fn exported(&self) -> Vec<(&str, &str)> {
+        self.vars.iter()
+            .filter(|&(_, var)| var.exported)
+            .filter_map(|(name, var)| match var.value {
+                Some(Value::Scalar(ref value)) => Some((name.as_str(), value.as_str())),
+                _ => None
+            })
+            .collect()
+    }
+
+    /// Returns every variable sorted by name
+    pub fn sorted(&self) -> Vec<(&str, &Variable)> {
+        let mut vars: Vec<_> = self.vars.iter().map(|(name, var)| (name.as_str(), var)).collect();
+        vars.sort_by_key(|&(name, _)| name);
+        vars
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        if let Some(saved) = self.scopes.pop() {
+            for (name, var) in saved.into_iter().rev() {
+                match var {
+                    Some(var) => self.vars.insert(name, var),
+                    None => self.vars.remove(&name)
+                };
+            }
+        }
+    }
+
+    /// Makes the variable local to the innermost scope, its current value is restored
+    /// when the scope is popped, returns false if there is no scope
+    pub fn make_local(&mut self, name: &str) -> bool {
+        let old = self.vars.get(name).cloned();
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                if !scope.iter().any(|&(ref saved, _)| saved == name) {
+                    scope.push((name.to_string(), old));
+                }
+                true
+            }
+            None => false
+        }
+    }
+}
+
+/// Returns true if `name` can be used as a variable name
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => chars.all(|c| c == '_' || c.is_ascii_alphanumeric()),
+        _ => false
+    }
+}
+
+/// Quotes a value so that it can be read back by the shell in double quotes
+pub fn quote_value(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' || c == '$' || c == '`' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_scopes() {
+        let mut vars = Variables::new();
+        vars.set("a", "global".to_string()).unwrap();
+        assert!(!vars.make_local("a"));
+        vars.push_scope();
+        assert!(vars.make_local("a"));
+        vars.set("a", "outer".to_string()).unwrap();
+        vars.push_scope();
+        assert!(vars.make_local("a"));
+        assert!(vars.make_local("b"));
+        vars.set("a", "inner".to_string()).unwrap();
+        vars.set("b", "inner".to_string()).unwrap();
+        // Making it local twice in the same scope keeps the first saved value
+        assert!(vars.make_local("a"));
+        vars.pop_scope();
+        assert_eq!(vars.get("a"), Some("outer"));
+        assert_eq!(vars.get("b"), None);
+        assert!(vars.get_var("b").is_none());
+        vars.pop_scope();
+        assert_eq!(vars.get("a"), Some("global"));
+        // Popping without a scope does nothing
+        vars.pop_scope();
+        assert_eq!(vars.get("a"), Some("global"));
+    }
+
+    #[test]
+    fn attributes_are_restored() {
+        let mut vars = Variables::new();
+        vars.set("a", "1".to_string()).unwrap();
+        vars.declare("a").exported = true;
+        vars.push_scope();
+        vars.make_local("a");
+        vars.declare("a").exported = false;
+        vars.declare("a").readonly = true;
+        vars.pop_scope();
+        let var = vars.get_var("a").unwrap();
+        assert!(var.exported && !var.readonly);
+    }
+
+    #[test]
+    fn readonly_variables() {
+        let mut vars = Variables::new();
+        vars.set("a", "1".to_string()).unwrap();
+        vars.declare("a").readonly = true;
+        assert!(vars.set("a", "2".to_string()).is_err());
+        assert!(vars.set_value("a", Value::Scalar("2".to_string())).is_err());
+        assert!(vars.unset("a").is_err());
+        assert_eq!(vars.get("a"), Some("1"));
+        // Declaring a readonly variable without a value keeps it readonly
+        vars.declare("b").readonly = true;
+        assert!(vars.set("b", "1".to_string()).is_err());
+        assert!(vars.get_var("b").unwrap().value.is_none());
+    }
+
+    #[test]
+    fn exported_filtering() {
+        let mut vars = Variables::new();
+        vars.set("plain", "1".to_string()).unwrap();
+        vars.set("shared", "2".to_string()).unwrap();
+        vars.declare("shared").exported = true;
+        // Declared without a value
+        vars.declare("empty").exported = true;
+        vars.set("unset", "3".to_string()).unwrap();
+        vars.declare("unset").exported = true;
+        vars.unset("unset").unwrap();
+        assert_eq!(vars.exported(), vec![("shared", "2")]);
+    }
+
+    #[test]
+    fn sorted_by_name() {
+        let mut vars = Variables::new();
+        for name in &["b", "_a", "a", "B"] {
+            vars.set(name, String::new()).unwrap();
+        }
+        let names: Vec<_> = vars.sorted().iter().map(|&(name, _)| name).collect();
+        assert_eq!(names, vec!["B", "_a", "a", "b"]);
+    }
+
+    #[test]
+    fn array_values() {
+        let indexed = Value::Indexed(vec![(1, "b".to_string()), (0, "a".to_string()), (7, "c".to_string())].into_iter().collect());
+        assert_eq!(indexed.scalar(), Some("a"));
+        assert_eq!(indexed.values(), vec!["a", "b", "c"]);
+        assert_eq!(indexed.keys(), vec!["0", "1", "7"]);
+        let assoc = Value::Assoc(vec![("y".to_string(), "2".to_string()), ("x".to_string(), "1".to_string())].into_iter().collect());
+        assert_eq!(assoc.scalar(), None);
+        assert_eq!(assoc.values(), vec!["1", "2"]);
+        assert_eq!(assoc.keys(), vec!["x", "y"]);
+        let scalar = Value::Scalar("s".to_string());
+        assert_eq!((scalar.values(), scalar.keys()), (vec!["s"], vec!["0".to_string()]));
+    }
+
+    #[test]
+    fn array_elements() {
+        let mut vars = Variables::new();
+        // A scalar becomes the element at 0
+        vars.set("a", "zero".to_string()).unwrap();
+        vars.set_index("a", 2, "two".to_string()).unwrap();
+        assert_eq!(vars.get_var("a").unwrap().value.as_ref().unwrap().values(), vec!["zero", "two"]);
+        vars.set("a", "first".to_string()).unwrap();
+        assert_eq!(vars.get("a"), Some("first"));
+        vars.unset_element("a", "0").unwrap();
+        assert_eq!(vars.get("a"), None);
+        assert!(vars.set_key("a", "k".to_string(), "v".to_string()).is_err());
+
+        vars.set_value("m", Value::Assoc(BTreeMap::new())).unwrap();
+        vars.set_key("m", "k".to_string(), "v".to_string()).unwrap();
+        assert!(vars.set_index("m", 0, "v".to_string()).is_err());
+        assert_eq!(vars.get_var("m").unwrap().value.as_ref().unwrap().keys(), vec!["k"]);
+        vars.unset_element("m", "k").unwrap();
+        assert!(vars.get_var("m").unwrap().value.as_ref().unwrap().keys().is_empty());
+
+        vars.declare("m").readonly = true;
+        assert!(vars.unset_element("m", "k").is_err());
+        // Unsetting an element of a missing array is not an error
+        assert!(vars.unset_element("missing", "0").is_ok());
+    }
+
+    #[test]
+    fn arrays_are_not_exported() {
+        let mut vars = Variables::new();
+        vars.set_index("arr", 0, "a".to_string()).unwrap();
+        vars.declare("arr").exported = true;
+        assert!(vars.exported().is_empty());
+    }
+
+    #[test]
+    fn valid_names() {
+        for name in &["a", "_", "_a1", "ABC_9"] {
+            assert!(is_valid_name(name), "{}", name);
+        }
+        for name in &["", "1a", "a-b", "a.b", "é", "a b"] {
+            assert!(!is_valid_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn quoted_values() {
+        assert_eq!(quote_value("a b"), "\"a b\"");
+        assert_eq!(quote_value("$x \"y\" \\ `z`"), "\"\\$x \\\"y\\\" \\\\ \\`z\\`\"");
+    }
+}
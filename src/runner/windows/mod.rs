@@ -19,6 +19,7 @@
 mod dir;
 
 use super::command::{Command, Method};
+use super::shell::Shell;
 
 use std::env::current_dir;
 
@@ -29,7 +30,7 @@ pub fn get_builtin(cmd_name: &str) -> Option<Method> {
     }
 }
 
-pub fn ls(_cmd: &Command) -> Option<i32> {
+pub fn ls(_shell: &mut Shell, _cmd: &Command) -> Option<i32> {
     let curr_dir = current_dir();
     match curr_dir {
         Ok(curr_dir) => {
@@ -0,0 +1,238 @@
+/*
+ * Project: rusth
+ * File: util/frecency.rs
+ * Copyright 2018 Caner Korkmaz (Kausta) <info@canerkorkmaz.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::history::get_home_file_path;
+
+/// Once the ranks add up to more than this, all of them are aged so old directories fade out
+const MAX_TOTAL_RANK: f64 = 9000.0;
+
+/// A directory visited with `cd`, ranked by how often and how recently it was visited
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub path: String,
+    pub rank: f64,
+    /// Seconds since the epoch of the last visit
+    pub time: u64,
+}
+
+impl Entry {
+    /// Score of the entry, the rank weighted by how long ago the directory was visited
+    pub fn frecency(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.time);
+        if age < 3600 {
+            self.rank * 4.0
+        } else if age < 86400 {
+            self.rank * 2.0
+        } else if age < 604800 {
+            self.rank / 2.0
+        } else {
+            self.rank / 4.0
+        }
+    }
+
+    /// Returns true if the patterns appear in the path in the given order
+    fn matches(&self, patterns: &[String], ignore_case: bool) -> bool {
+        let path = if ignore_case { self.path.to_lowercase() } else { self.path.clone() };
+        let mut rest = path.as_str();
+        for pattern in patterns {
+            let pattern = if ignore_case { pattern.to_lowercase() } else { pattern.clone() };
+            match rest.find(&pattern) {
+                Some(idx) => rest = &rest[idx + pattern.len()..],
+                None => return false
+            }
+        }
+        true
+    }
+}
+
+pub fn get_frecency_path() -> PathBuf {
+    get_home_file_path(".rusth.z")
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+/// Reads the database, which has a `path|rank|time` line for each directory,
+/// malformed lines are skipped
+pub fn load(path: &Path) -> Vec<Entry> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Vec::new()
+    };
+    BufReader::new(file).lines()
+        .filter_map(|line| line.ok())
+        .filter_map(|line| {
+            let mut fields = line.rsplitn(3, '|');
+            let time = fields.next()?.parse().ok()?;
+            let rank = fields.next()?.parse().ok()?;
+            let path = fields.next()?.to_string();
+            Some(Entry { path, rank, time })
+        })
+        .collect()
+}
+
+/// Writes the database to a temporary file and renames it into place,
+/// so other shells never read it half written
+pub fn save(path: &Path, entries: &[Entry]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", process::id()));
+    let tmp = PathBuf::from(tmp);
+    let res = write_entries(&tmp, entries).and_then(|_| fs::rename(&tmp, path));
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res
+}
+
+fn write_entries(path: &Path, entries: &[Entry]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    for entry in entries {
+        writeln!(file, "{}|{}|{}", entry.path, entry.rank, entry.time)?;
+    }
+    Ok(())
+}
+
+/// Records a visit to the directory in the database
+pub fn record(dir: &str) -> io::Result<()> {
+    let path = get_frecency_path();
+    let mut entries = load(&path);
+    update(&mut entries, dir, now());
+    save(&path, &entries)
+}
+
+/// Ranks the visit to the directory, the ranks are aged when their total is too high
+fn update(entries: &mut Vec<Entry>, dir: &str, now: u64) {
+    match entries.iter_mut().find(|entry| entry.path == dir) {
+        Some(entry) => {
+            entry.rank += 1.0;
+            entry.time = now;
+        }
+        None => entries.push(Entry { path: dir.to_string(), rank: 1.0, time: now })
+    }
+    let total: f64 = entries.iter().map(|entry| entry.rank).sum();
+    if total > MAX_TOTAL_RANK {
+        for entry in entries.iter_mut() {
+            entry.rank *= 0.99;
+        }
+        entries.retain(|entry| entry.rank >= 1.0);
+    }
+}
+
+/// Returns the existing directories matching all the patterns in order, the best match last.
+/// The patterns are case sensitive unless nothing matches them that way.
+pub fn candidates(patterns: &[String]) -> Vec<(f64, String)> {
+    let entries: Vec<Entry> = load(&get_frecency_path()).into_iter()
+        .filter(|entry| Path::new(&entry.path).is_dir())
+        .collect();
+    let now = now();
+    let mut found: Vec<(f64, String)> = Vec::new();
+    for &ignore_case in [false, true].iter() {
+        found = entries.iter()
+            .filter(|entry| entry.matches(patterns, ignore_case))
+            .map(|entry| (entry.frecency(now), entry.path.clone()))
+            .collect();
+        if !found.is_empty() {
+            break;
+        }
+    }
+    found.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal));
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn entry(path: &str, rank: f64, time: u64) -> Entry {
+        Entry { path: path.to_string(), rank, time }
+    }
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    #[test]
+    fn matches_in_order() {
+        let entry = entry("/home/user/Projects/rusth", 1.0, 0);
+        assert!(entry.matches(&patterns(&["rusth"]), false));
+        assert!(entry.matches(&patterns(&["home", "Proj", "th"]), false));
+        assert!(!entry.matches(&patterns(&["rusth", "home"]), false));
+        assert!(!entry.matches(&patterns(&["projects"]), false));
+        assert!(entry.matches(&patterns(&["projects"]), true));
+        assert!(!entry.matches(&patterns(&["rusth", "rusth"]), false));
+        assert!(entry.matches(&[], false));
+    }
+
+    #[test]
+    fn frecency_by_age() {
+        let entry = entry("/tmp", 8.0, 1_000_000);
+        assert_eq!(entry.frecency(1_000_000), 32.0);
+        assert_eq!(entry.frecency(1_000_000 + 7200), 16.0);
+        assert_eq!(entry.frecency(1_000_000 + 2 * 86400), 4.0);
+        assert_eq!(entry.frecency(1_000_000 + 30 * 86400), 2.0);
+        // A clock that went back counts as a recent visit
+        assert_eq!(entry.frecency(0), 32.0);
+    }
+
+    #[test]
+    fn update_ranks() {
+        let mut entries = vec![entry("/a", 3.0, 10)];
+        update(&mut entries, "/a", 20);
+        update(&mut entries, "/b", 30);
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].rank, entries[0].time), (4.0, 20));
+        assert_eq!((entries[1].path.as_str(), entries[1].rank, entries[1].time), ("/b", 1.0, 30));
+    }
+
+    #[test]
+    fn update_ages_ranks() {
+        let mut entries = vec![entry("/a", MAX_TOTAL_RANK, 10), entry("/b", 1.0, 10)];
+        update(&mut entries, "/c", 20);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, "/a");
+        assert_eq!(entries[0].rank, MAX_TOTAL_RANK * 0.99);
+    }
+
+    #[test]
+    fn load_save_round_trip() {
+        let dir = env::temp_dir().join(format!("rusth-frecency-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("z");
+        assert!(load(&path).is_empty());
+        let entries = vec![entry("/a|b", 2.5, 100), entry("/c d", 1.0, 200)];
+        save(&path, &entries).unwrap();
+        save(&path, &entries).unwrap();
+        let loaded = load(&path);
+        assert_eq!(loaded.len(), 2);
+        assert_eq!((loaded[0].path.as_str(), loaded[0].rank, loaded[0].time), ("/a|b", 2.5, 100));
+        assert_eq!((loaded[1].path.as_str(), loaded[1].rank, loaded[1].time), ("/c d", 1.0, 200));
+        // Only the database is left, the temporary file was renamed
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::write(&path, "/x|1|2\nmalformed\n/y|z|3\n").unwrap();
+        assert_eq!(load(&path).len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+/*
+ * Project: rusth
+ * File: util/glob.rs
+ * Copyright 2018 Caner Korkmaz (Kausta) [info@canerkorkmaz.com]
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::fs;
+use std::path::Path;
+
+/// Returns true if `c` has a special meaning in a pattern
+pub fn is_special(c: char) -> bool {
+    match c {
+        '*' | '?' | '[' | ']' | '\\' => true,
+        _ => false
+    }
+}
+
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_chars(&pattern, &text)
+}
+
+fn matches_chars(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` in the pattern and the text position it is tried against
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() {
+            if pattern[p] == '*' {
+                star = Some((p, t));
+                p += 1;
+                continue;
+            }
+            if let Some(next) = match_single(pattern, p, text[t]) {
+                p = next;
+                t += 1;
+                continue;
+            }
+        }
+        // Mismatch, let the last star consume one more char if there is one
+        match star {
+            Some((star_p, star_t)) => {
+                p = star_p + 1;
+                t = star_t + 1;
+                star = Some((star_p, star_t + 1));
+            }
+            None => return false
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches a single char against the pattern element at `p`,
+/// returns the index after the element if it matches
+fn match_single(pattern: &[char], p: usize, c: char) -> Option<usize> {
+    match pattern[p] {
+        '?' => Some(p + 1),
+        '[' => match match_bracket(pattern, p, c) {
+            Some((true, next)) => Some(next),
+            Some((false, _)) => None,
+            // No closing bracket, so it is a literal `[`
+            None => if c == '[' { Some(p + 1) } else { None }
+        },
+        '\\' if p + 1 < pattern.len() => if pattern[p + 1] == c { Some(p + 2) } else { None },
+        literal => if literal == c { Some(p + 1) } else { None }
+    }
+}
+
+/// Matches a bracket expression starting at `start`,
+/// returns whether it matched and the index after the closing `]`, or None if it is not closed
+fn match_bracket(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = i < pattern.len() && (pattern[i] == '!' || pattern[i] == '^');
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while i < pattern.len() {
+        let mut lo = pattern[i];
+        if lo == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+        if lo == '[' && pattern.get(i + 1) == Some(&':') {
+            if let Some(end) = find_class_end(pattern, i + 2) {
+                let class: String = pattern[i + 2..end].iter().collect();
+                matched |= matches_class(&class, c);
+                i = end + 2;
+                continue;
+            }
+        }
+        if lo == '\\' && i + 1 < pattern.len() {
+            i += 1;
+            lo = pattern[i];
+        }
+        i += 1;
+        let mut hi = lo;
+        if i + 1 < pattern.len() && pattern[i] == '-' && pattern[i + 1] != ']' {
+            hi = pattern[i + 1];
+            i += 2;
+            if hi == '\\' && i < pattern.len() {
+                hi = pattern[i];
+                i += 1;
+            }
+        }
+        if lo <= c && c <= hi {
+            matched = true;
+        }
+    }
+    None
+}
+
+fn find_class_end(pattern: &[char], from: usize) -> Option<usize> {
+    (from..pattern.len().saturating_sub(1)).find(|&i| pattern[i] == ':' && pattern[i + 1] == ']')
+}
+
+fn matches_class(class: &str, c: char) -> bool {
+    match class {
+        "alpha" => c.is_alphabetic(),
+        "digit" => c.is_ascii_digit(),
+        "alnum" => c.is_alphanumeric(),
+        "upper" => c.is_uppercase(),
+        "lower" => c.is_lowercase(),
+        "space" => c.is_whitespace(),
+        "blank" => c == ' ' || c == '\t',
+        "punct" => c.is_ascii_punctuation(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        "cntrl" => c.is_control(),
+        "print" => !c.is_control(),
+        "graph" => !c.is_control() && !c.is_whitespace(),
+        _ => false
+    }
+}
+
+/// Returns the paths matching the pattern in sorted order, `/` is only matched literally
+/// and names starting with `.` are only matched by a pattern starting with `.`
+pub fn expand_path(pattern: &str) -> Vec<String> {
+    let (mut paths, rest) = if pattern.starts_with('/') {
+        (vec!["/".to_string()], &pattern[1..])
+    } else {
+        (vec![String::new()], pattern)
+    };
+    for component in rest.split('/') {
+        let mut next = Vec::new();
+        for path in paths {
+            if !has_magic(component) {
+                let joined = format!("{}{}", path, unescape(component));
+                if Path::new(&joined).exists() {
+                    next.push(joined);
+                }
+                continue;
+            }
+            let dir = if path.is_empty() { "." } else { path.as_str() };
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue
+            };
+            let mut names: Vec<String> = entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .filter(|name| !name.starts_with('.') || component.starts_with('.') || component.starts_with("\\."))
+                .filter(|name| matches(component, name))
+                .collect();
+            names.sort();
+            next.extend(names.into_iter().map(|name| format!("{}{}", path, name)));
+        }
+        paths = next.into_iter().map(|path| path + "/").collect();
+    }
+    // Remove the slash added after the last component
+    paths.into_iter().map(|mut path| {
+        path.pop();
+        path
+    }).filter(|path| !path.is_empty()).collect()
+}
+
+/// Returns true if the pattern has any unescaped special characters
+pub fn has_magic(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Removes the escaping backslashes from a pattern
+pub fn unescape(pattern: &str) -> String {
+    let mut res = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    res.push(c);
+                }
+            }
+            _ => res.push(c)
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    /// Creates a directory with the given files, `name/` entries are directories
+    fn temp_tree(name: &str, entries: &[&str]) -> String {
+        let root = env::temp_dir().join(format!("rusth-glob-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        for entry in entries {
+            let path = root.join(entry);
+            if entry.ends_with('/') {
+                fs::create_dir_all(&path).unwrap();
+            } else {
+                fs::write(&path, "").unwrap();
+            }
+        }
+        root.display().to_string()
+    }
+
+    fn expand(root: &str, pattern: &str) -> Vec<String> {
+        expand_path(&format!("{}/{}", root, pattern)).into_iter()
+            .map(|path| path[root.len() + 1..].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn hidden_files() {
+        let root = temp_tree("hidden", &[".hidden", "shown", ".dir/", ".dir/.inner", ".dir/file"]);
+        assert_eq!(expand(&root, "*"), vec!["shown"]);
+        assert_eq!(expand(&root, ".*"), vec![".dir", ".hidden"]);
+        assert_eq!(expand(&root, "\\.h*"), vec![".hidden"]);
+        assert_eq!(expand(&root, "?hidden"), Vec::<String>::new());
+        assert_eq!(expand(&root, "[.]hidden"), Vec::<String>::new());
+        assert_eq!(expand(&root, ".dir/*"), vec![".dir/file"]);
+        assert_eq!(expand(&root, ".*/.*"), vec![".dir/.inner"]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn escaped_characters() {
+        let root = temp_tree("escaped", &["a*b", "axb", "[x]", "x", "q?"]);
+        assert_eq!(expand(&root, "a*b"), vec!["a*b", "axb"]);
+        assert_eq!(expand(&root, "a\\*b"), vec!["a*b"]);
+        assert_eq!(expand(&root, "\\[x]"), vec!["[x]"]);
+        assert_eq!(expand(&root, "[x]"), vec!["x"]);
+        assert_eq!(expand(&root, "q\\?"), vec!["q?"]);
+        assert_eq!(expand(&root, "a\\*c"), Vec::<String>::new());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn absolute_patterns() {
+        let root = temp_tree("absolute", &["one/", "one/a.rs", "one/b.txt", "two/", "two/c.rs", "three"]);
+        assert_eq!(expand(&root, "*/*.rs"), vec!["one/a.rs", "two/c.rs"]);
+        assert_eq!(expand(&root, "*/"), vec!["one/", "two/"]);
+        assert_eq!(expand(&root, "t*"), vec!["three", "two"]);
+        assert_eq!(expand(&root, "one/missing*"), Vec::<String>::new());
+        assert_eq!(expand(&root, "missing/*"), Vec::<String>::new());
+        assert!(expand_path("/*").iter().all(|path| path.starts_with('/') && !path[1..].contains('/')));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn magic_and_unescape() {
+        assert!(has_magic("*.rs"));
+        assert!(has_magic("a[bc]"));
+        assert!(!has_magic("a\\*b"));
+        assert!(!has_magic("plain"));
+        assert_eq!(unescape("a\\*b\\\\c\\"), "a*b\\c");
+    }
+}
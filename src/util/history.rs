@@ -20,8 +20,13 @@ use std::env::home_dir;
 use std::path::PathBuf;
 
 pub fn get_history_text_path() -> PathBuf {
+    get_home_file_path(".rusth.history")
+}
+
+/// Returns the path of a file in the home directory, or in the current one if there is no home
+pub fn get_home_file_path(name: &str) -> PathBuf {
     match home_dir() {
-        Some(path) => path.join(".rusth.history"),
-        None => PathBuf::from(".rusth.history")
+        Some(path) => path.join(name),
+        None => PathBuf::from(name)
     }
 }
\ No newline at end of file
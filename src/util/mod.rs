@@ -17,4 +17,6 @@
 */
 
 pub mod prompt;
-pub mod history;
\ No newline at end of file
+pub mod history;
+pub mod frecency;
+pub mod glob;
\ No newline at end of file
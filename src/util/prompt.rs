@@ -24,6 +24,8 @@ use ansi_term;
 
 static PROMPT_ANSI: &'static str = "\x1b[1;32m>>\x1b[0m ";
 static PROMPT_NORMAL: &'static str = ">> ";
+static CONTINUATION_ANSI: &'static str = "\x1b[1;32m>\x1b[0m ";
+static CONTINUATION_NORMAL: &'static str = "> ";
 
 pub struct Prompt<'a> {
     pub prompt_base: &'a str,
@@ -72,6 +74,12 @@ impl<'a> Prompt<'a> {
         prompt
     }
 
+    /// Prompt shown while reading the rest of an incomplete command
+    pub fn make_continuation_prompt(&self) -> String {
+        let prompt = if self.have_ansi { CONTINUATION_ANSI } else { CONTINUATION_NORMAL };
+        prompt.to_string()
+    }
+
     fn reset_state(&mut self) {
         self.return_code = None;
     }